@@ -7,13 +7,21 @@ psql -v ON_ERROR_STOP=1 -U "$POSTGRES_USER" <<-EOSQL
 CREATE EXTENSION IF NOT EXISTS timescaledb;
 
 CREATE TABLE logs (
+    id BIGSERIAL,
     time TIMESTAMP,
+    ingest_time TIMESTAMP,
     level TEXT,
     source TEXT,
     words TEXT[],
     logdata JSONB
 );
 
+CREATE TABLE rejected_logs (
+    time TIMESTAMP,
+    reason TEXT,
+    payload TEXT
+);
+
 CREATE TABLE filters (
     query TEXT,
     name TEXT PRIMARY KEY
@@ -31,13 +39,37 @@ CREATE TABLE cols (
   metric_agg TEXT
 );
 
+CREATE TABLE view_versions (
+    filter_name TEXT,
+    version INT,
+    time TIMESTAMP DEFAULT now(),
+    author TEXT,
+    query TEXT,
+    columns JSONB,
+    PRIMARY KEY (filter_name, version)
+);
+
 CREATE INDEX idx_logdata ON logs USING GIN (logdata);
 CREATE INDEX idx_words ON logs USING GIN (words);
+CREATE INDEX idx_time_id ON logs (time, id);
+CREATE INDEX idx_id ON logs (id);
 
 INSERT INTO filters (name, query) VALUES ('logs', 'true');
 INSERT INTO column_filter (column_name, filter_name) VALUES ('Data', 'logs');
 INSERT INTO cols (name, query) VALUES ('Data', 'logdata');
 
+INSERT INTO view_versions (filter_name, version, query, columns)
+SELECT filters.name, 1, filters.query, COALESCE(
+    (SELECT jsonb_agg(jsonb_build_object(
+            'name', cols.name,
+            'query', COALESCE(cols.query, ''),
+            'metric_agg', COALESCE(cols.metric_agg, '')
+        ) ORDER BY column_filter.idx)
+        FROM column_filter JOIN cols ON cols.name = column_filter.column_name
+        WHERE column_filter.filter_name = filters.name),
+    '[]')
+FROM filters;
+
 
 SELECT create_hypertable('logs', 'time');
 SELECT set_chunk_time_interval('logs', INTERVAL '10 minutes');
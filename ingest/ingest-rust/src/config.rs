@@ -0,0 +1,164 @@
+use std::path::Path;
+
+use amqprs::{
+    channel::{Channel, QueueBindArguments, QueueDeclareArguments},
+    connection::{Connection, OpenConnectionArguments},
+};
+use clap::Args;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// RabbitMQ broker and routing shared by the producers and the consumer.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AmqpConfig {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub password: String,
+    pub vhost: String,
+    pub exchange: String,
+    pub queue: String,
+    pub routing_key: String,
+}
+
+impl Default for AmqpConfig {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_owned(),
+            port: 5672,
+            user: "guest".to_owned(),
+            password: "guest".to_owned(),
+            vhost: "/".to_owned(),
+            exchange: "amq.topic".to_owned(),
+            queue: "amqprs.examples.basic".to_owned(),
+            routing_key: "amqprs.example".to_owned(),
+        }
+    }
+}
+
+impl AmqpConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.host.is_empty() {
+            return Err("amqp.host must not be empty".to_owned());
+        }
+        if self.port == 0 {
+            return Err("amqp.port must not be 0".to_owned());
+        }
+        if self.queue.is_empty() {
+            return Err("amqp.queue must not be empty".to_owned());
+        }
+        Ok(())
+    }
+
+    /// Copy of the config with the password hidden, for display.
+    pub fn redacted(&self) -> Self {
+        Self {
+            password: "<redacted>".to_owned(),
+            ..self.clone()
+        }
+    }
+
+    /// Open a connection and a channel, declaring the durable queue and
+    /// binding it to the exchange.
+    pub async fn connect(&self) -> Result<(Connection, Channel), amqprs::error::Error> {
+        let connection = Connection::open(
+            OpenConnectionArguments::new(&self.host, self.port, &self.user, &self.password)
+                .virtual_host(&self.vhost),
+        )
+        .await?;
+        let channel = connection.open_channel(None).await?;
+        channel
+            .queue_declare(QueueDeclareArguments::durable_client_named(&self.queue))
+            .await?;
+        channel
+            .queue_bind(QueueBindArguments::new(
+                &self.queue,
+                &self.exchange,
+                &self.routing_key,
+            ))
+            .await?;
+        Ok((connection, channel))
+    }
+}
+
+/// Command line and environment overrides of [`AmqpConfig`].
+#[derive(Debug, Clone, Default, Args)]
+pub struct AmqpArgs {
+    /// RabbitMQ host
+    #[arg(long = "amqp-host", env = "LOGDOG_AMQP_HOST")]
+    pub host: Option<String>,
+    /// RabbitMQ port
+    #[arg(long = "amqp-port", env = "LOGDOG_AMQP_PORT")]
+    pub port: Option<u16>,
+    /// RabbitMQ user
+    #[arg(long = "amqp-user", env = "LOGDOG_AMQP_USER")]
+    pub user: Option<String>,
+    /// RabbitMQ password
+    #[arg(
+        long = "amqp-password",
+        env = "LOGDOG_AMQP_PASSWORD",
+        hide_env_values = true
+    )]
+    pub password: Option<String>,
+    /// RabbitMQ virtual host
+    #[arg(long = "amqp-vhost", env = "LOGDOG_AMQP_VHOST")]
+    pub vhost: Option<String>,
+    /// Exchange the queue is bound to
+    #[arg(long, env = "LOGDOG_EXCHANGE")]
+    pub exchange: Option<String>,
+    /// Durable queue holding the logs
+    #[arg(long, env = "LOGDOG_QUEUE")]
+    pub queue: Option<String>,
+    /// Routing key binding the queue to the exchange
+    #[arg(long, env = "LOGDOG_ROUTING_KEY")]
+    pub routing_key: Option<String>,
+}
+
+impl AmqpArgs {
+    pub fn apply(self, config: &mut AmqpConfig) {
+        override_with(&mut config.host, self.host);
+        override_with(&mut config.port, self.port);
+        override_with(&mut config.user, self.user);
+        override_with(&mut config.password, self.password);
+        override_with(&mut config.vhost, self.vhost);
+        override_with(&mut config.exchange, self.exchange);
+        override_with(&mut config.queue, self.queue);
+        override_with(&mut config.routing_key, self.routing_key);
+    }
+}
+
+pub fn override_with<T>(value: &mut T, with: Option<T>) {
+    if let Some(with) = with {
+        *value = with;
+    }
+}
+
+/// Read a TOML config file, or the defaults when there is none.
+pub fn load_file<T: DeserializeOwned + Default>(path: Option<&Path>) -> Result<T, String> {
+    let Some(path) = path else {
+        return Ok(T::default());
+    };
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| format!("cannot read {}: {}", path.display(), err))?;
+    toml::from_str(&content).map_err(|err| format!("invalid config {}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_and_overrides() {
+        let mut config: AmqpConfig = toml::from_str("host = \"rabbit\"\nport = 5673").unwrap();
+        assert_eq!(config.host, "rabbit");
+        assert_eq!(config.queue, AmqpConfig::default().queue);
+        AmqpArgs {
+            port: Some(5674),
+            ..Default::default()
+        }
+        .apply(&mut config);
+        assert_eq!(config.port, 5674);
+        assert_eq!(config.host, "rabbit");
+        assert!(toml::from_str::<AmqpConfig>("hots = \"typo\"").is_err());
+    }
+}
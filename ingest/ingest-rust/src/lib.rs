@@ -0,0 +1,10 @@
+//! Code shared by the logdog ingest binaries.
+
+pub mod config;
+pub mod line;
+pub mod link;
+pub mod multiline;
+pub mod signal;
+pub mod spool;
+pub mod syslog;
+pub mod tail;
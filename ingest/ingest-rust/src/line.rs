@@ -0,0 +1,244 @@
+use serde_json::{Map, Value};
+
+/// Turn an input line into a log object.
+///
+/// JSON objects are kept as they are, logfmt lines (`key=value key2="a b"`)
+/// become objects and anything else is wrapped as `{"message": line}`.
+/// Blank lines give `None`.
+pub fn parse_line(line: &str, detect_level: bool) -> Option<Value> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    if line.trim().is_empty() {
+        return None;
+    }
+    if line.trim_start().starts_with('{') {
+        if let Ok(value @ Value::Object(_)) = serde_json::from_str(line) {
+            return Some(value);
+        }
+    }
+    if let Some(mut fields) = parse_logfmt(line) {
+        if detect_level {
+            if let Some(level) = fields
+                .get("level")
+                .and_then(Value::as_str)
+                .and_then(normalize_level)
+            {
+                fields.insert("level".to_owned(), level.into());
+            }
+        }
+        return Some(fields.into());
+    }
+    let mut fields = Map::new();
+    fields.insert("message".to_owned(), line.into());
+    if detect_level {
+        if let Some(level) = detect_text_level(line) {
+            fields.insert("level".to_owned(), level.into());
+        }
+    }
+    Some(fields.into())
+}
+
+/// Parse an event spanning several lines, such as a stack trace: its first
+/// line as [`parse_line`] does, with the others appended to its `message`,
+/// or to the logfmt `msg`.
+pub fn parse_event(lines: &[String], detect_level: bool) -> Option<Value> {
+    let (first, rest) = lines.split_first()?;
+    let Some(mut log) = parse_line(first, detect_level) else {
+        return parse_event(rest, detect_level);
+    };
+    if rest.is_empty() {
+        return Some(log);
+    }
+    let continuation = rest.join("\n");
+    let fields = log.as_object_mut()?;
+    let key = ["message", "msg"]
+        .into_iter()
+        .find(|key| fields.get(*key).is_some_and(Value::is_string))
+        .unwrap_or(if fields.contains_key("message") {
+            "continuation"
+        } else {
+            "message"
+        });
+    let text = match fields.get(key).and_then(Value::as_str) {
+        Some(text) => format!("{}\n{}", text, continuation),
+        None => continuation,
+    };
+    fields.insert(key.to_owned(), text.into());
+    Some(log)
+}
+
+/// Map the usual spellings of a level to the ones logdog uses.
+fn normalize_level(level: &str) -> Option<&'static str> {
+    match level.to_ascii_uppercase().as_str() {
+        "TRACE" => Some("TRACE"),
+        "DEBUG" | "DBG" => Some("DEBUG"),
+        "INFO" | "INF" | "NOTICE" => Some("INFO"),
+        "WARN" | "WARNING" | "WRN" => Some("WARNING"),
+        "ERROR" | "ERR" => Some("ERROR"),
+        "FATAL" | "CRITICAL" | "CRIT" | "PANIC" | "EMERG" | "ALERT" => Some("CRITICAL"),
+        _ => None,
+    }
+}
+
+/// Look for a level in the first words of a plain text line: the very first
+/// word in any case (`error: ...`, `[warn] ...`), or a later upper case one
+/// following a timestamp (`2024-03-24 17:53:44 ERROR ...`).
+fn detect_text_level(line: &str) -> Option<&'static str> {
+    line.split_whitespace()
+        .take(4)
+        .enumerate()
+        .find_map(|(i, word)| {
+            let word = word.trim_matches(|c: char| "[]()<>:|".contains(c));
+            if i > 0 && word != word.to_ascii_uppercase() {
+                return None;
+            }
+            normalize_level(word)
+        })
+}
+
+/// Read a quoted logfmt value, after its opening quote, returning the value
+/// and what follows its closing quote.
+fn read_quoted(text: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &text[i + 1..])),
+            '\\' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                other => value.push(other),
+            },
+            _ => value.push(c),
+        }
+    }
+    None
+}
+
+/// Unquoted values that are JSON numbers or booleans keep their type.
+fn bare_value(raw: &str) -> Value {
+    match raw {
+        "true" => true.into(),
+        "false" => false.into(),
+        _ => match serde_json::from_str::<serde_json::Number>(raw) {
+            Ok(number) => number.into(),
+            Err(_) => raw.into(),
+        },
+    }
+}
+
+/// Parse a logfmt line, or `None` if any word is not a `key=value` pair.
+fn parse_logfmt(line: &str) -> Option<Map<String, Value>> {
+    let mut fields = Map::new();
+    let mut rest = line.trim();
+    while !rest.is_empty() {
+        let (key, after) = rest.split_once('=')?;
+        if key.is_empty() || key.contains(|c: char| c.is_whitespace() || c == '"') {
+            return None;
+        }
+        let value = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let (value, after) = read_quoted(quoted)?;
+                if !after.is_empty() && !after.starts_with(char::is_whitespace) {
+                    return None;
+                }
+                rest = after;
+                Value::String(value)
+            }
+            None => {
+                let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                let raw = &after[..end];
+                if raw.contains('"') {
+                    return None;
+                }
+                rest = &after[end..];
+                bare_value(raw)
+            }
+        };
+        fields.insert(key.to_owned(), value);
+        rest = rest.trim_start();
+    }
+    (!fields.is_empty()).then_some(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(parse_line("  \n", true), None);
+        assert_eq!(
+            parse_line("{\"level\": \"info\", \"a\": [1]}\n", true),
+            Some(json!({"level": "info", "a": [1]}))
+        );
+        assert_eq!(
+            parse_line(
+                r#"level=warn msg="disk \"sda\" full" used=0.98 ok=false code=007 path=/a=b"#,
+                true
+            ),
+            Some(json!({
+                "level": "WARNING",
+                "msg": "disk \"sda\" full",
+                "used": 0.98,
+                "ok": false,
+                "code": "007",
+                "path": "/a=b",
+            }))
+        );
+        assert_eq!(
+            parse_line("level=warn", false),
+            Some(json!({"level": "warn"}))
+        );
+        assert_eq!(
+            parse_line("GET /index status=200", true),
+            Some(json!({"message": "GET /index status=200"}))
+        );
+        assert_eq!(
+            parse_line("{not json", true),
+            Some(json!({"message": "{not json"}))
+        );
+    }
+
+    #[test]
+    fn test_parse_event() {
+        let lines = |lines: &[&str]| lines.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            parse_event(
+                &lines(&["ERROR: boom", "Traceback:", "  File \"a.py\""]),
+                true
+            ),
+            Some(json!({"level": "ERROR", "message": "ERROR: boom\nTraceback:\n  File \"a.py\""}))
+        );
+        assert_eq!(
+            parse_event(&lines(&["level=error msg=failed", "  at A.b"]), false),
+            Some(json!({"level": "error", "msg": "failed\n  at A.b"}))
+        );
+        assert_eq!(
+            parse_event(&lines(&[r#"{"message": {"a": 1}}"#, "  at A.b"]), false),
+            Some(json!({"message": {"a": 1}, "continuation": "  at A.b"}))
+        );
+        assert_eq!(
+            parse_event(&lines(&["", "  at A.b"]), false),
+            Some(json!({"message": "  at A.b"}))
+        );
+    }
+
+    #[test]
+    fn test_detect_level() {
+        for (line, level) in [
+            ("error: cannot open file", Some("ERROR")),
+            ("[Warn] slow query", Some("WARNING")),
+            ("2024-03-24 17:53:44 CRIT disk failure", Some("CRITICAL")),
+            ("2024-03-24 17:53:44 <info> started", None),
+            ("Information about the error", None),
+        ] {
+            assert_eq!(detect_text_level(line), level, "{line}");
+        }
+        assert_eq!(
+            parse_line("E: nothing", true),
+            Some(json!({"message": "E: nothing"}))
+        );
+    }
+}
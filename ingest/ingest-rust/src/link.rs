@@ -0,0 +1,189 @@
+use std::time::{Duration, Instant};
+
+use amqprs::{
+    callbacks::ChannelCallback,
+    channel::{BasicPublishArguments, Channel, ConfirmSelectArguments},
+    connection::Connection,
+    Ack, BasicProperties, Cancel, CloseChannel, Nack, Return,
+};
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::config::AmqpConfig;
+
+/// First wait before reconnecting to RabbitMQ, doubled after each failure.
+pub const MIN_BACKOFF: Duration = Duration::from_millis(500);
+pub const MAX_BACKOFF: Duration = Duration::from_secs(30);
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest wait for the broker to confirm a message before reconnecting.
+pub const CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Forwards the publisher confirms of a channel: `true` for an ack, `false`
+/// for a nack.
+struct Confirms(mpsc::UnboundedSender<bool>);
+
+#[async_trait]
+impl ChannelCallback for Confirms {
+    async fn close(
+        &mut self,
+        _: &Channel,
+        close: CloseChannel,
+    ) -> Result<(), amqprs::error::Error> {
+        warn!("RabbitMQ closed the channel: {}", close);
+        Ok(())
+    }
+
+    async fn cancel(&mut self, _: &Channel, _: Cancel) -> Result<(), amqprs::error::Error> {
+        Ok(())
+    }
+
+    async fn flow(&mut self, _: &Channel, _: bool) -> Result<bool, amqprs::error::Error> {
+        Ok(true)
+    }
+
+    async fn publish_ack(&mut self, _: &Channel, _: Ack) {
+        self.0.send(true).ok();
+    }
+
+    async fn publish_nack(&mut self, _: &Channel, _: Nack) {
+        self.0.send(false).ok();
+    }
+
+    async fn publish_return(&mut self, _: &Channel, _: Return, _: BasicProperties, _: Vec<u8>) {}
+}
+
+struct Open {
+    connection: Connection,
+    channel: Channel,
+    /// Confirms of the channel, in confirm mode
+    confirmed: Option<mpsc::UnboundedReceiver<bool>>,
+}
+
+/// A RabbitMQ connection for publishing, reopened with an exponential
+/// backoff once it fails.
+///
+/// In confirm mode, a message only counts as published once the broker
+/// acks it.
+pub struct Link {
+    amqp: AmqpConfig,
+    confirm: bool,
+    open: Option<Open>,
+    backoff: Duration,
+    retry_at: Instant,
+}
+
+impl Link {
+    pub fn new(amqp: AmqpConfig) -> Self {
+        Self {
+            amqp,
+            confirm: false,
+            open: None,
+            backoff: MIN_BACKOFF,
+            retry_at: Instant::now(),
+        }
+    }
+
+    /// A link whose channel is in confirm mode.
+    pub fn with_confirms(amqp: AmqpConfig) -> Self {
+        Self {
+            confirm: true,
+            ..Self::new(amqp)
+        }
+    }
+
+    pub fn amqp(&self) -> &AmqpConfig {
+        &self.amqp
+    }
+
+    async fn connect(&self) -> Result<Open, amqprs::error::Error> {
+        let (connection, channel) = self.amqp.connect().await?;
+        let mut confirmed = None;
+        if self.confirm {
+            let (confirms, receiver) = mpsc::unbounded_channel();
+            channel.register_callback(Confirms(confirms)).await?;
+            channel
+                .confirm_select(ConfirmSelectArguments::default())
+                .await?;
+            confirmed = Some(receiver);
+        }
+        Ok(Open {
+            connection,
+            channel,
+            confirmed,
+        })
+    }
+
+    /// Whether the link is open, connecting first if the backoff allows it.
+    pub async fn connected(&mut self) -> bool {
+        if let Some(open) = &self.open {
+            if open.connection.is_open() && open.channel.is_open() {
+                return true;
+            }
+            warn!("lost the connection to RabbitMQ");
+            self.open = None;
+        }
+        if Instant::now() < self.retry_at {
+            return false;
+        }
+        match tokio::time::timeout(CONNECT_TIMEOUT, self.connect()).await {
+            Ok(Ok(open)) => {
+                info!("connected to RabbitMQ at {}", self.amqp.host);
+                self.backoff = MIN_BACKOFF;
+                self.open = Some(open);
+                true
+            }
+            failure => {
+                let reason = match failure {
+                    Ok(Err(err)) => err.to_string(),
+                    _ => "timed out".to_owned(),
+                };
+                warn!(
+                    "connecting to RabbitMQ failed: {}, retrying in {:?}",
+                    reason, self.backoff
+                );
+                self.retry_at = Instant::now() + self.backoff;
+                self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+                false
+            }
+        }
+    }
+
+    /// Publish a message on the open link, and in confirm mode wait for the
+    /// broker to ack it. The link is dropped when this fails, the next
+    /// [`Link::connected`] reconnects.
+    pub async fn publish(
+        &mut self,
+        args: BasicPublishArguments,
+        properties: BasicProperties,
+        content: Vec<u8>,
+    ) -> Result<(), String> {
+        let Some(open) = self.open.as_mut() else {
+            return Err("not connected to RabbitMQ".to_owned());
+        };
+        let failure = match open.channel.basic_publish(properties, content, args).await {
+            Ok(()) => match open.confirmed.as_mut() {
+                None => return Ok(()),
+                Some(confirmed) => {
+                    match tokio::time::timeout(CONFIRM_TIMEOUT, confirmed.recv()).await {
+                        Ok(Some(true)) => return Ok(()),
+                        Ok(Some(false)) => "the broker nacked it".to_owned(),
+                        Ok(None) => "the channel closed before the broker confirmed it".to_owned(),
+                        Err(_) => "the broker did not confirm it in time".to_owned(),
+                    }
+                }
+            },
+            Err(err) => err.to_string(),
+        };
+        self.open = None;
+        Err(failure)
+    }
+
+    /// Close the connection, which sends what is still buffered.
+    pub async fn close(&mut self) {
+        if let Some(open) = self.open.take() {
+            open.channel.close().await.ok();
+            open.connection.close().await.ok();
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+use regex::Regex;
+
+/// When a line continues the previous event, such as the frames of a stack
+/// trace, instead of starting a new one.
+pub struct MultilineRules {
+    /// Lines matching it start an event, the others continue it
+    pub start: Option<Regex>,
+    /// Only lines matching it continue an event
+    pub continuation: Option<Regex>,
+    /// Longest event, in lines
+    pub max_lines: usize,
+    /// Time without a new line after which an event is complete
+    pub timeout: Duration,
+}
+
+impl MultilineRules {
+    fn continues(&self, line: &str) -> bool {
+        self.start
+            .as_ref()
+            .is_none_or(|start| !start.is_match(line))
+            && self
+                .continuation
+                .as_ref()
+                .is_none_or(|continuation| continuation.is_match(line))
+    }
+}
+
+/// Lines of an event, with the tag of its last line.
+#[derive(Debug, PartialEq)]
+pub struct Event<T> {
+    pub lines: Vec<String>,
+    pub tag: T,
+}
+
+struct Pending<T> {
+    event: Event<T>,
+    last_line: Instant,
+}
+
+/// Join the lines of each stream, keyed by `K`, into events.
+pub struct Joiner<K, T> {
+    rules: MultilineRules,
+    pending: HashMap<K, Pending<T>>,
+}
+
+impl<K: Hash + Eq + Clone, T> Joiner<K, T> {
+    pub fn new(rules: MultilineRules) -> Self {
+        Self {
+            rules,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Add a line of the `key` stream, returning the event it completes:
+    /// the previous one when this line starts a new event, or this one when
+    /// it reaches `max_lines`.
+    pub fn push(&mut self, key: K, line: String, tag: T, now: Instant) -> Option<Event<T>> {
+        let line = line.trim_end_matches(['\r', '\n']).to_owned();
+        let mut complete = None;
+        match self.pending.get_mut(&key) {
+            Some(pending) if self.rules.continues(&line) => {
+                pending.event.lines.push(line);
+                pending.event.tag = tag;
+                pending.last_line = now;
+            }
+            _ => {
+                let pending = Pending {
+                    event: Event {
+                        lines: vec![line],
+                        tag,
+                    },
+                    last_line: now,
+                };
+                complete = self.pending.insert(key.clone(), pending);
+            }
+        }
+        if self.pending[&key].event.lines.len() >= self.rules.max_lines {
+            return self.pending.remove(&key).map(|pending| pending.event);
+        }
+        complete.map(|pending| pending.event)
+    }
+
+    /// When the oldest pending event times out.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending
+            .values()
+            .map(|pending| pending.last_line + self.rules.timeout)
+            .min()
+    }
+
+    /// Take the events without a new line for `timeout`.
+    pub fn expired(&mut self, now: Instant) -> Vec<(K, Event<T>)> {
+        let expired: Vec<K> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.last_line + self.rules.timeout <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|key| {
+                let pending = self.pending.remove(&key)?;
+                Some((key, pending.event))
+            })
+            .collect()
+    }
+
+    /// Take every pending event, at the end of the input.
+    pub fn drain(&mut self) -> Vec<(K, Event<T>)> {
+        self.pending
+            .drain()
+            .map(|(key, pending)| (key, pending.event))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_joiner() {
+        let rules = MultilineRules {
+            start: Some(Regex::new(r"^\d{4}-").unwrap()),
+            continuation: None,
+            max_lines: 3,
+            timeout: Duration::from_secs(1),
+        };
+        let mut joiner = Joiner::new(rules);
+        let now = Instant::now();
+        assert_eq!(joiner.push("a", "2024-03-24 boom\n".into(), 1, now), None);
+        assert_eq!(joiner.push("a", "  at Foo.bar\n".into(), 2, now), None);
+        assert_eq!(joiner.push("b", "2024-03-24 other".into(), 1, now), None);
+        assert_eq!(
+            joiner.push("a", "2024-03-24 next".into(), 3, now),
+            Some(Event {
+                lines: vec!["2024-03-24 boom".into(), "  at Foo.bar".into()],
+                tag: 2,
+            })
+        );
+        joiner.push("a", "  at 1".into(), 4, now);
+        assert_eq!(
+            joiner.push("a", "  at 2".into(), 5, now),
+            Some(Event {
+                lines: vec!["2024-03-24 next".into(), "  at 1".into(), "  at 2".into()],
+                tag: 5,
+            })
+        );
+        assert_eq!(joiner.next_deadline(), Some(now + Duration::from_secs(1)));
+        assert!(joiner.expired(now).is_empty());
+        let expired = joiner.expired(now + Duration::from_secs(1));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0, "b");
+        assert_eq!(joiner.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_continuation_rule() {
+        let rules = MultilineRules {
+            start: None,
+            continuation: Some(Regex::new(r"^(\s|Traceback|\w+Error:)").unwrap()),
+            max_lines: 100,
+            timeout: Duration::from_secs(1),
+        };
+        let mut joiner = Joiner::new(rules);
+        let now = Instant::now();
+        joiner.push((), "ERROR failed".into(), (), now);
+        joiner.push((), "Traceback (most recent call last):".into(), (), now);
+        joiner.push((), "  File \"a.py\", line 1".into(), (), now);
+        joiner.push((), "ValueError: nope".into(), (), now);
+        let event = joiner.push((), "INFO next".into(), (), now).unwrap();
+        assert_eq!(event.lines.len(), 4);
+        assert_eq!(joiner.drain()[0].1.lines, vec!["INFO next".to_owned()]);
+    }
+}
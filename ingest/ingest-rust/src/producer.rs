@@ -1,100 +1,749 @@
-use amqprs::{
-    channel::{BasicPublishArguments, QueueBindArguments, QueueDeclareArguments},
-    connection::{Connection, OpenConnectionArguments},
-    BasicProperties,
+use amqprs::{channel::BasicPublishArguments, BasicProperties};
+use clap::Parser;
+use logdog_rust::{
+    config::{AmqpArgs, AmqpConfig},
+    line::parse_event,
+    link::Link,
+    multiline::{Event, Joiner, MultilineRules},
+    signal::shutdown_signal,
+    spool::Spool,
+    syslog,
+    tail::{Checkpoint, FileId, FileOffset, FileTailer, TailLine},
 };
+use regex::Regex;
+use tokio::signal::unix::SignalKind;
+use tracing::{error, info, metadata, warn};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
-use std::sync::mpsc::channel;
-use std::time::Duration;
-use std::{io::stdin, thread::sleep};
+use std::collections::{HashMap, HashSet};
+use std::io::{stdin, BufRead, BufReader};
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Lines read ahead of publishing, the readers wait when it is full.
+const LINE_CAPACITY: usize = 16384;
+
+/// How often the spool is retried while no logs come in.
+const IDLE_REPLAY_INTERVAL: Duration = Duration::from_secs(1);
+
+const TAIL_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Read logs from stdin or from a command it runs, or tail files and receive
+/// syslog, and publish them to RabbitMQ.
+///
+/// Each line is a JSON object, a logfmt line or plain text, which is sent
+/// as `{"message": line}`.
+#[derive(Debug, Parser)]
+#[command(name = "logdog-producer")]
+struct ProducerArgs {
+    /// Set `level` from plain text prefixes such as `ERROR:` or `[warn]`,
+    /// and normalize the logfmt ones
+    #[arg(long, env = "LOGDOG_DETECT_LEVEL")]
+    detect_level: bool,
+    /// Tail the files matching these glob patterns instead of reading stdin
+    #[arg(long = "file", env = "LOGDOG_FILES", value_delimiter = ',', num_args = 1..)]
+    files: Vec<String>,
+    /// Where the offsets of the tailed files are saved
+    #[arg(
+        long,
+        env = "LOGDOG_CHECKPOINT",
+        default_value = "logdog-producer.checkpoint.json"
+    )]
+    checkpoint: PathBuf,
+    /// Receive syslog datagrams on this address, such as 0.0.0.0:514
+    #[arg(long, env = "LOGDOG_SYSLOG_UDP")]
+    syslog_udp: Option<String>,
+    /// Accept syslog streams on this address
+    #[arg(long, env = "LOGDOG_SYSLOG_TCP")]
+    syslog_tcp: Option<String>,
+    /// Receive syslog datagrams on this Unix socket, such as /dev/log
+    #[arg(long, env = "LOGDOG_SYSLOG_UNIX")]
+    syslog_unix: Option<PathBuf>,
+    /// Where batches are kept while RabbitMQ cannot be reached
+    #[arg(long, env = "LOGDOG_SPOOL_DIR", default_value = "logdog-spool")]
+    spool_dir: PathBuf,
+    /// Most bytes kept in the spool, the oldest batches are dropped beyond
+    #[arg(long, env = "LOGDOG_SPOOL_MAX_BYTES", default_value_t = 256 * 1024 * 1024)]
+    spool_max_bytes: u64,
+    /// Publish a batch once it holds this many records
+    #[arg(long, env = "LOGDOG_BATCH_RECORDS", default_value_t = 1000)]
+    batch_records: usize,
+    /// Publish a batch once it is this big
+    #[arg(long, env = "LOGDOG_BATCH_BYTES", default_value_t = 1024 * 1024)]
+    batch_bytes: usize,
+    /// Publish a batch at most this long after its first record
+    #[arg(long, env = "LOGDOG_BATCH_MILLIS", default_value_t = 200)]
+    batch_millis: u64,
+    /// Lines matching this regex start an event, the others are appended to
+    /// the previous one, such as the frames of a stack trace
+    #[arg(long, env = "LOGDOG_MULTILINE_START")]
+    multiline_start: Option<Regex>,
+    /// Lines matching this regex are appended to the previous event
+    #[arg(long, env = "LOGDOG_MULTILINE_CONTINUE")]
+    multiline_continue: Option<Regex>,
+    /// Most lines joined in an event
+    #[arg(long, env = "LOGDOG_MULTILINE_MAX_LINES", default_value_t = 500)]
+    multiline_max_lines: usize,
+    /// An event is complete after this long without a new line
+    #[arg(long, env = "LOGDOG_MULTILINE_TIMEOUT_MILLIS", default_value_t = 1000)]
+    multiline_timeout_millis: u64,
+    /// Run this command, after `--`, and publish its stdout and stderr lines
+    /// instead of reading stdin, then exit with its status
+    #[arg(
+        last = true,
+        conflicts_with_all = ["files", "syslog_udp", "syslog_tcp", "syslog_unix"]
+    )]
+    command: Vec<String>,
+    #[command(flatten)]
+    amqp: AmqpArgs,
+}
+
+enum Input {
+    Stdin(String),
+    File(TailLine),
+    Syslog(serde_json::Value),
+    Child {
+        source: Arc<str>,
+        stream: &'static str,
+        line: String,
+    },
+}
+
+/// Where a line comes from, to join multiline events of each stream apart
+/// and label their logs.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+struct Origin {
+    /// Path of the tailed file, or the command and pid of the child
+    source: Option<Arc<str>>,
+    /// `stdout` or `stderr` of the child
+    stream: Option<&'static str>,
+}
+
+impl ProducerArgs {
+    fn has_syslog(&self) -> bool {
+        self.syslog_udp.is_some() || self.syslog_tcp.is_some() || self.syslog_unix.is_some()
+    }
+
+    /// Join multiline events of stdin, the tailed files and the child
+    /// streams, each on its own, if there is a rule for it.
+    fn joiner(&mut self) -> Option<Joiner<Origin, Option<FileOffset>>> {
+        if self.multiline_start.is_none() && self.multiline_continue.is_none() {
+            return None;
+        }
+        Some(Joiner::new(MultilineRules {
+            start: self.multiline_start.take(),
+            continuation: self.multiline_continue.take(),
+            max_lines: self.multiline_max_lines.max(1),
+            timeout: Duration::from_millis(self.multiline_timeout_millis),
+        }))
+    }
+}
+
+/// Send the lines of `reader` to `tx` until its end, which drops the sender.
+fn read_lines(mut reader: impl BufRead, tx: mpsc::Sender<Input>, input: impl Fn(String) -> Input) {
+    loop {
+        let mut line = Vec::new();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&line).into_owned();
+                if tx.blocking_send(input(line)).is_err() {
+                    break;
+                }
+            }
+            Err(err) => {
+                error!("reading input failed: {}", err);
+                break;
+            }
+        }
+    }
+}
+
+/// Send the lines of a child stream to `tx`, from a thread of its own.
+fn read_stream(
+    stream: impl std::io::Read + Send + 'static,
+    source: Arc<str>,
+    name: &'static str,
+    tx: mpsc::Sender<Input>,
+) {
+    std::thread::spawn(move || {
+        read_lines(BufReader::new(stream), tx, |line| Input::Child {
+            source: source.clone(),
+            stream: name,
+            line,
+        })
+    });
+}
+
+/// Run the command, sending the lines of its stdout and stderr to `tx`.
+fn spawn_command(command: &[String], tx: &mpsc::Sender<Input>) -> std::io::Result<Child> {
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let source: Arc<str> = format!("{}[{}]", command[0], child.id()).into();
+    info!("running {}", source);
+    if let Some(stdout) = child.stdout.take() {
+        read_stream(stdout, source.clone(), "stdout", tx.clone());
+    }
+    if let Some(stderr) = child.stderr.take() {
+        read_stream(stderr, source, "stderr", tx.clone());
+    }
+    Ok(child)
+}
+
+/// Pass a signal on to the command, which stops the way it would without
+/// the producer. Its output is still read until it closes.
+fn forward_signal(child: &Child, signal: SignalKind) {
+    info!(
+        "forwarding signal {} to {}",
+        signal.as_raw_value(),
+        child.id()
+    );
+    // SAFETY: kill only sends a signal, to a child that is not reaped yet
+    if unsafe { libc::kill(child.id() as libc::pid_t, signal.as_raw_value()) } != 0 {
+        warn!(
+            "forwarding the signal failed: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+/// Start the syslog listeners asked for, sending their records to `tx`.
+fn listen_syslog(args: &ProducerArgs, tx: &mpsc::Sender<Input>) -> std::io::Result<()> {
+    let emit = |tx: mpsc::Sender<Input>| {
+        move |log| {
+            let _res = tx.blocking_send(Input::Syslog(log));
+        }
+    };
+    if let Some(addr) = &args.syslog_udp {
+        syslog::listen_udp(addr, emit(tx.clone()))?;
+    }
+    if let Some(addr) = &args.syslog_tcp {
+        syslog::listen_tcp(addr, emit(tx.clone()))?;
+    }
+    if let Some(path) = &args.syslog_unix {
+        syslog::listen_unix(path, emit(tx.clone()))?;
+    }
+    Ok(())
+}
+
+/// A JSON array of logs being filled, serialized as records come in so its
+/// size is known.
+struct Batch {
+    content: Vec<u8>,
+    records: usize,
+    /// Offsets of the tailed files once this batch is published
+    positions: HashMap<FileId, FileOffset>,
+    /// When the batch must be published, set by its first record
+    deadline: Option<tokio::time::Instant>,
+}
+
+impl Batch {
+    fn new() -> Self {
+        Self {
+            content: b"[".to_vec(),
+            records: 0,
+            positions: HashMap::new(),
+            deadline: None,
+        }
+    }
+
+    fn push(&mut self, log: &serde_json::Value, max_delay: Duration) {
+        if self.records == 0 {
+            self.deadline = Some(tokio::time::Instant::now() + max_delay);
+        } else {
+            self.content.push(b',');
+        }
+        serde_json::to_writer(&mut self.content, log).unwrap();
+        self.records += 1;
+    }
+
+    /// Add an event, and the offset following it in its tailed file.
+    fn push_event(
+        &mut self,
+        origin: Origin,
+        event: Event<Option<FileOffset>>,
+        detect_level: bool,
+        max_delay: Duration,
+    ) {
+        if let Some(mut log) = parse_event(&event.lines, detect_level) {
+            if let Some(source) = &origin.source {
+                log["source"] = source.as_ref().into();
+            }
+            if let Some(stream) = origin.stream {
+                log["stream"] = stream.into();
+            }
+            self.push(&log, max_delay);
+        }
+        if let Some(position) = event.tag {
+            self.positions.insert(position.id(), position);
+        }
+    }
+
+    /// The closed JSON array, leaving an empty batch behind.
+    fn take(&mut self) -> Self {
+        let mut batch = std::mem::replace(self, Self::new());
+        batch.content.push(b']');
+        batch
+    }
+}
+
+/// Totals logged when the producer exits.
+struct Stats {
+    started: Instant,
+    records: u64,
+    batches: u64,
+    bytes: u64,
+}
+
+impl Stats {
+    fn log(&self, spool: &Spool) {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        info!(
+            records = self.records,
+            batches = self.batches,
+            bytes = self.bytes,
+            elapsed_secs = format!("{:.1}", elapsed),
+            records_per_sec = format!("{:.0}", self.records as f64 / elapsed.max(0.001)),
+            spooled_batches = spool.len(),
+            dropped_batches = spool.dropped,
+            "producer stats"
+        );
+    }
+}
+
+/// Publishes batches to RabbitMQ, reconnecting with an exponential backoff,
+/// and spools them on disk while the broker is away.
+///
+/// Batches are persistent and published one at a time in confirm mode, a
+/// batch only counts as sent, and leaves the spool, once the broker acks it.
+struct Publisher {
+    args: BasicPublishArguments,
+    properties: BasicProperties,
+    link: Link,
+    spool: Spool,
+}
+
+impl Publisher {
+    fn new(amqp: AmqpConfig, spool: Spool) -> Self {
+        Self {
+            args: BasicPublishArguments::new(&amqp.exchange, &amqp.routing_key),
+            properties: BasicProperties::default().with_delivery_mode(2).finish(),
+            link: Link::with_confirms(amqp),
+            spool,
+        }
+    }
+
+    /// Publish now and wait for the broker to confirm it, giving the batch
+    /// back if it could not be sent.
+    async fn try_publish(&mut self, content: Vec<u8>) -> Result<(), Vec<u8>> {
+        if !self.link.connected().await {
+            return Err(content);
+        }
+        let args = self.args.clone();
+        let properties = self.properties.clone();
+        match self.link.publish(args, properties, content.clone()).await {
+            Ok(()) => Ok(()),
+            Err(failure) => {
+                warn!("publishing failed: {}", failure);
+                Err(content)
+            }
+        }
+    }
+
+    /// Publish a batch, or spool it while RabbitMQ cannot be reached.
+    /// Once there is a spool, batches queue behind it to keep their order.
+    /// Fails when the batch could be neither published nor spooled.
+    async fn publish(&mut self, content: Vec<u8>) -> Result<(), ()> {
+        let content = if self.spool.is_empty() {
+            match self.try_publish(content).await {
+                Ok(()) => return Ok(()),
+                Err(content) => content,
+            }
+        } else {
+            content
+        };
+        let spooled = self.spool.push(&content);
+        if let Err(err) = &spooled {
+            error!("spooling a batch failed, it is lost: {}", err);
+        }
+        self.replay().await;
+        spooled.map_err(drop)
+    }
+
+    /// Publish the spooled batches, oldest first, until the spool is empty
+    /// or RabbitMQ is away again.
+    async fn replay(&mut self) {
+        while !self.spool.is_empty() {
+            let content = match self.spool.front() {
+                Ok(Some(content)) => content,
+                Ok(None) => return,
+                Err(err) => {
+                    error!("reading the spool failed, skipping a batch: {}", err);
+                    if let Err(err) = self.spool.pop_front() {
+                        error!("removing a spooled batch failed: {}", err);
+                        return;
+                    }
+                    continue;
+                }
+            };
+            if self.try_publish(content).await.is_err() {
+                return;
+            }
+            if let Err(err) = self.spool.pop_front() {
+                error!("removing a spooled batch failed: {}", err);
+                return;
+            }
+            if self.spool.is_empty() {
+                info!("spool replayed");
+            }
+        }
+    }
+
+    /// Close the connection, which sends what is still buffered.
+    async fn close(mut self) {
+        self.link.close().await;
+    }
+}
+
+/// File offsets saved as the lines before them are published.
+struct Offsets {
+    checkpoint: Checkpoint,
+    /// Files with a lost batch, whose offsets stay before it so that the
+    /// next run reads it again
+    held: HashSet<FileId>,
+}
+
+/// Publish a batch, then save the offsets of its lines.
+async fn flush(
+    batch: Batch,
+    publisher: &mut Publisher,
+    offsets: &mut Option<Offsets>,
+    stats: &mut Stats,
+) {
+    // Blank lines still move the offsets, without anything to publish
+    let mut published = true;
+    if batch.records > 0 {
+        stats.records += batch.records as u64;
+        stats.batches += 1;
+        stats.bytes += batch.content.len() as u64;
+        published = publisher.publish(batch.content).await.is_ok();
+    }
+    // Offsets only move once their lines are confirmed or spooled
+    if let Some(offsets) = offsets.as_mut().filter(|_| !batch.positions.is_empty()) {
+        for (id, position) in batch.positions {
+            if !published {
+                offsets.held.insert(id);
+            } else if !offsets.held.contains(&id) {
+                offsets.checkpoint.files.insert(id, position);
+            }
+        }
+        if let Err(err) = offsets.checkpoint.save() {
+            warn!("saving the checkpoint failed: {}", err);
+        }
+    }
+}
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
 async fn main() {
-    // construct a subscriber that prints formatted traces to stdout
-    // global subscriber with log level according to RUST_LOG
+    // construct a subscriber that prints formatted traces to stderr, keeping
+    // stdout free, with log level according to RUST_LOG
     tracing_subscriber::registry()
-        .with(fmt::layer())
-        .with(EnvFilter::from_default_env())
+        .with(fmt::layer().with_writer(std::io::stderr))
+        .with(
+            EnvFilter::builder()
+                .with_default_directive(metadata::LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
         .try_init()
         .ok();
 
-    // open a connection to RabbitMQ server
-    let connection = Connection::open(&OpenConnectionArguments::new(
-        "localhost",
-        5672,
-        "guest",
-        "guest",
-    ))
-    .await
-    .unwrap();
-
-    // open a channel on the connection
-    let amqp_channel = connection.open_channel(None).await.unwrap();
-
-    // declare a durable queue
-    let (queue_name, _, _) = amqp_channel
-        .queue_declare(QueueDeclareArguments::durable_client_named(
-            "amqprs.examples.basic",
-        ))
-        .await
-        .unwrap()
-        .unwrap();
-
-    // bind the queue to exchange
-    let rounting_key = "amqprs.example";
-    let exchange_name = "amq.topic";
-    amqp_channel
-        .queue_bind(QueueBindArguments::new(
-            &queue_name,
-            exchange_name,
-            rounting_key,
-        ))
-        .await
-        .unwrap();
-
-    let args = BasicPublishArguments::new(exchange_name, rounting_key);
-    let (tx, rx) = channel();
-    let _reader_manager = tokio::spawn(async move {
-        loop {
-            let mut line = String::new();
-            let read_line = stdin().read_line(&mut line);
-            if read_line.is_ok() {
-                let _res = tx.send(line);
+    let mut args = ProducerArgs::parse();
+    let mut amqp = AmqpConfig::default();
+    std::mem::take(&mut args.amqp).apply(&mut amqp);
+    if let Err(err) = amqp.validate() {
+        eprintln!("logdog-producer: {}", err);
+        std::process::exit(2);
+    }
+    let detect_level = args.detect_level;
+    let mut joiner = args.joiner();
+    let (tx, mut rx) = mpsc::channel(LINE_CAPACITY);
+    let mut offsets = None;
+    if let Err(err) = listen_syslog(&args, &tx) {
+        eprintln!("logdog-producer: cannot listen for syslog: {}", err);
+        std::process::exit(2);
+    }
+    let mut child = None;
+    if !args.command.is_empty() {
+        match spawn_command(&args.command, &tx) {
+            Ok(spawned) => child = Some(spawned),
+            Err(err) => {
+                eprintln!("logdog-producer: cannot run {}: {}", args.command[0], err);
+                std::process::exit(2);
             }
         }
-    });
+    } else if args.files.is_empty() && !args.has_syslog() {
+        // At EOF, dropping the sender ends the producer
+        let tx = tx.clone();
+        std::thread::spawn(move || read_lines(stdin().lock(), tx, Input::Stdin));
+    } else if !args.files.is_empty() {
+        let tailer = Checkpoint::load(&args.checkpoint).and_then(|mut loaded| {
+            let tailer = FileTailer::new(&args.files, loaded.files.clone())?;
+            // Forget the offsets of files rotated away since the last run
+            let matching = tailer.matching_files();
+            loaded.files.retain(|id, _| matching.contains(id));
+            Ok((loaded, tailer))
+        });
+        let (loaded, mut tailer) = match tailer {
+            Ok(tailer) => tailer,
+            Err(err) => {
+                eprintln!("logdog-producer: {}", err);
+                std::process::exit(2);
+            }
+        };
+        offsets = Some(Offsets {
+            checkpoint: loaded,
+            held: HashSet::new(),
+        });
+        let tx = tx.clone();
+        std::thread::spawn(move || loop {
+            tailer.poll(&mut |line| {
+                let _res = tx.blocking_send(Input::File(line));
+            });
+            sleep(TAIL_POLL_INTERVAL);
+        });
+    }
+    drop(tx);
+
+    let spool = match Spool::open(&args.spool_dir, args.spool_max_bytes) {
+        Ok(spool) => spool,
+        Err(err) => {
+            eprintln!(
+                "logdog-producer: cannot open spool {}: {}",
+                args.spool_dir.display(),
+                err
+            );
+            std::process::exit(2);
+        }
+    };
+    if !spool.is_empty() {
+        info!("{} spooled batches to replay", spool.len());
+    }
+    let mut publisher = Publisher::new(amqp, spool);
+    let max_delay = Duration::from_millis(args.batch_millis);
+    let mut stats = Stats {
+        started: Instant::now(),
+        records: 0,
+        batches: 0,
+        bytes: 0,
+    };
+    let mut batch = Batch::new();
+    let mut interrupt = std::pin::pin!(shutdown_signal());
     loop {
-        let mut final_str: String = "[".to_owned();
-        for i in 0..32 {
-            let read_event = rx.try_recv();
-            if read_event.is_err() {
-                break;
+        let deadline = batch
+            .deadline
+            .unwrap_or_else(|| tokio::time::Instant::now() + IDLE_REPLAY_INTERVAL);
+        let deadline = match joiner.as_ref().and_then(Joiner::next_deadline) {
+            Some(pending) => deadline.min(tokio::time::Instant::from_std(pending)),
+            None => deadline,
+        };
+        let input = tokio::select! {
+            input = rx.recv() => input,
+            _ = tokio::time::sleep_until(deadline) => {
+                if let Some(joiner) = &mut joiner {
+                    for (origin, event) in joiner.expired(Instant::now()) {
+                        batch.push_event(origin, event, detect_level, max_delay);
+                    }
+                }
+                if batch.deadline.is_some_and(|deadline| deadline <= tokio::time::Instant::now()) {
+                    flush(batch.take(), &mut publisher, &mut offsets, &mut stats).await;
+                } else if batch.records == 0 {
+                    publisher.replay().await;
+                }
+                continue;
+            }
+            signal = &mut interrupt => match &child {
+                // Keep publishing its output until it exits
+                Some(child) => {
+                    forward_signal(child, signal);
+                    interrupt.set(shutdown_signal());
+                    continue;
+                }
+                None => None,
+            },
+        };
+        let Some(input) = input else {
+            break;
+        };
+        let (origin, line, position) = match input {
+            Input::Syslog(log) => {
+                batch.push(&log, max_delay);
+                (Origin::default(), None, None)
+            }
+            Input::Stdin(line) => (Origin::default(), Some(line), None),
+            Input::File(line) => {
+                let origin = Origin {
+                    source: Some(line.path),
+                    stream: None,
+                };
+                (origin, Some(line.text), line.position)
+            }
+            Input::Child {
+                source,
+                stream,
+                line,
+            } => {
+                let origin = Origin {
+                    source: Some(source),
+                    stream: Some(stream),
+                };
+                (origin, Some(line), None)
+            }
+        };
+        if let Some(line) = line {
+            let event = match &mut joiner {
+                Some(joiner) => joiner.push(origin.clone(), line, position, Instant::now()),
+                None => Some(Event {
+                    lines: vec![line],
+                    tag: position,
+                }),
+            };
+            if let Some(event) = event {
+                batch.push_event(origin, event, detect_level, max_delay);
             }
-            let read_bytes = read_event.unwrap();
-            let mut format_prefix = ",";
-            if i == 0 {
-                format_prefix = "";
-            }
-            final_str.push_str(format!("{}{}", format_prefix, read_bytes).as_str());
-        }
-        final_str.push_str("]");
-        if final_str.len() > 2 {
-            amqp_channel
-                .basic_publish(
-                    BasicProperties::default(),
-                    final_str.as_bytes().to_vec(),
-                    args.clone(),
-                )
-                .await
-                .unwrap();
-        } else {
-            sleep(Duration::from_millis(200));
         }
+        if batch.records >= args.batch_records || batch.content.len() >= args.batch_bytes {
+            flush(batch.take(), &mut publisher, &mut offsets, &mut stats).await;
+        }
+    }
+    for (origin, event) in joiner.iter_mut().flat_map(Joiner::drain) {
+        batch.push_event(origin, event, detect_level, max_delay);
+    }
+    if batch.records > 0 || !batch.positions.is_empty() {
+        flush(batch.take(), &mut publisher, &mut offsets, &mut stats).await;
+    }
+    stats.log(&publisher.spool);
+    if !publisher.spool.is_empty() {
+        warn!(
+            "{} batches stay in the spool for the next run",
+            publisher.spool.len()
+        );
+    }
+    publisher.close().await;
+    if let Some(mut child) = child {
+        // Its output is closed, so it exited or is about to
+        let code = match child.wait() {
+            Ok(status) => {
+                info!("{} exited with {}", args.command[0], status);
+                status
+                    .code()
+                    .unwrap_or_else(|| 128 + status.signal().unwrap_or(0))
+            }
+            Err(err) => {
+                error!("waiting for {} failed: {}", args.command[0], err);
+                1
+            }
+        };
+        std::process::exit(code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_forward_signal() {
+        let command = [
+            "sh".to_owned(),
+            "-c".to_owned(),
+            "trap 'echo stopping; exit 3' TERM; echo ready; while :; do sleep 0.05; done"
+                .to_owned(),
+        ];
+        let (tx, mut rx) = mpsc::channel(16);
+        let mut child = spawn_command(&command, &tx).unwrap();
+        drop(tx);
+        let mut lines = Vec::new();
+        while let Some(Input::Child { line, .. }) = rx.recv().await {
+            if lines.is_empty() {
+                forward_signal(&child, SignalKind::terminate());
+            }
+            lines.push(line);
+        }
+        // The output written while stopping is read before the pipes close
+        assert_eq!(lines, vec!["ready\n", "stopping\n"]);
+        assert_eq!(child.wait().unwrap().code(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_batch() {
+        let mut batch = Batch::new();
+        assert_eq!(batch.deadline, None);
+        batch.push(&json!({"a": 1}), Duration::from_millis(200));
+        let deadline = batch.deadline;
+        batch.push(&json!({"message": "b"}), Duration::from_millis(200));
+        assert_eq!(batch.deadline, deadline);
+        let full = batch.take();
+        assert_eq!(full.records, 2);
+        let logs: serde_json::Value = serde_json::from_slice(&full.content).unwrap();
+        assert_eq!(logs, json!([{"a": 1}, {"message": "b"}]));
+        assert_eq!((batch.records, batch.content.as_slice()), (0, &b"["[..]));
+    }
+
+    #[tokio::test]
+    async fn test_flush_lost_batch() {
+        let dir = std::env::temp_dir().join(format!("logdog-flush-{}", std::process::id()));
+        let spool = Spool::open(&dir.join("spool"), 1 << 20).unwrap();
+        // Neither a broker to publish to nor a spool to write to
+        std::fs::remove_dir_all(dir.join("spool")).unwrap();
+        let amqp = AmqpConfig {
+            port: 1,
+            ..AmqpConfig::default()
+        };
+        let mut publisher = Publisher::new(amqp, spool);
+        let mut offsets = Some(Offsets {
+            checkpoint: Checkpoint::load(&dir.join("checkpoint.json")).unwrap(),
+            held: HashSet::new(),
+        });
+        let mut stats = Stats {
+            started: Instant::now(),
+            records: 0,
+            batches: 0,
+            bytes: 0,
+        };
+        let mut batch = Batch::new();
+        batch.push(&json!({"message": "a"}), Duration::ZERO);
+        batch.positions.insert(
+            (1, 2),
+            FileOffset {
+                dev: 1,
+                inode: 2,
+                offset: 2,
+            },
+        );
+        flush(batch, &mut publisher, &mut offsets, &mut stats).await;
+        // Later batches of the file do not move its offset past the lost one
+        let mut batch = Batch::new();
+        batch.positions.insert(
+            (1, 2),
+            FileOffset {
+                dev: 1,
+                inode: 2,
+                offset: 3,
+            },
+        );
+        flush(batch, &mut publisher, &mut offsets, &mut stats).await;
+        let offsets = offsets.unwrap();
+        assert!(offsets.checkpoint.files.is_empty());
+        assert!(offsets.held.contains(&(1, 2)));
+        std::fs::remove_dir_all(&dir).ok();
     }
-    // explicitly close
-    /*
-    amqp_channel.close().await.unwrap();
-    connection.close().await.unwrap();
-    */
 }
@@ -0,0 +1,532 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use amqprs::{channel::BasicPublishArguments, BasicProperties};
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use clap::Parser;
+use logdog_rust::{
+    config::{load_file, override_with, AmqpArgs, AmqpConfig},
+    link::Link,
+    signal::shutdown_signal,
+};
+use reqwest::{header::ACCEPT, Client, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::{sync::Mutex, time::MissedTickBehavior};
+use tracing::{error, info, metadata, warn};
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+/// A Prometheus endpoint, with labels added to each of its samples.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Target {
+    pub url: String,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PromConfig {
+    pub targets: Vec<Target>,
+    /// Seconds between two scrapes of a target
+    pub interval_secs: u64,
+    /// Seconds before a scrape is given up
+    pub timeout_secs: u64,
+    /// Most samples published in one AMQP message
+    pub samples_per_message: usize,
+    pub amqp: AmqpConfig,
+}
+
+impl Default for PromConfig {
+    fn default() -> Self {
+        Self {
+            targets: vec![],
+            interval_secs: 15,
+            timeout_secs: 10,
+            samples_per_message: 500,
+            amqp: AmqpConfig::default(),
+        }
+    }
+}
+
+impl PromConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        self.amqp.validate()?;
+        if self.targets.is_empty() {
+            return Err("at least one target is needed".to_owned());
+        }
+        for target in self.targets.iter() {
+            let url = Url::parse(&target.url)
+                .map_err(|err| format!("invalid target url {}: {}", target.url, err))?;
+            if !matches!(url.scheme(), "http" | "https") {
+                return Err(format!("target url {} is not http(s)", target.url));
+            }
+        }
+        for (name, value) in [
+            ("interval_secs", self.interval_secs as usize),
+            ("timeout_secs", self.timeout_secs as usize),
+            ("samples_per_message", self.samples_per_message),
+        ] {
+            if value == 0 {
+                return Err(format!("{} must be at least 1", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Scrape Prometheus endpoints and publish their samples to RabbitMQ as logs.
+///
+/// Settings come from the defaults, then the config file, then environment
+/// variables, then command line flags.
+#[derive(Debug, Parser)]
+#[command(name = "prom-producer")]
+struct PromArgs {
+    /// TOML config file
+    #[arg(long, env = "LOGDOG_CONFIG")]
+    config: Option<PathBuf>,
+    /// Print the resolved config and exit
+    #[arg(long)]
+    print_config: bool,
+    /// Endpoint to scrape, replacing the targets of the config file
+    #[arg(long = "target", env = "LOGDOG_PROM_TARGETS", value_delimiter = ',')]
+    targets: Option<Vec<String>>,
+    #[arg(long, env = "LOGDOG_PROM_INTERVAL_SECS")]
+    interval_secs: Option<u64>,
+    #[arg(long, env = "LOGDOG_PROM_TIMEOUT_SECS")]
+    timeout_secs: Option<u64>,
+    #[arg(long, env = "LOGDOG_PROM_SAMPLES_PER_MESSAGE")]
+    samples_per_message: Option<usize>,
+    #[command(flatten)]
+    amqp: AmqpArgs,
+}
+
+impl PromArgs {
+    fn resolve(self) -> Result<PromConfig, String> {
+        let mut config: PromConfig = load_file(self.config.as_deref())?;
+        let targets = self.targets.map(|urls| {
+            urls.into_iter()
+                .map(|url| Target {
+                    url,
+                    labels: BTreeMap::new(),
+                })
+                .collect()
+        });
+        override_with(&mut config.targets, targets);
+        override_with(&mut config.interval_secs, self.interval_secs);
+        override_with(&mut config.timeout_secs, self.timeout_secs);
+        override_with(&mut config.samples_per_message, self.samples_per_message);
+        self.amqp.apply(&mut config.amqp);
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Sample {
+    name: String,
+    labels: BTreeMap<String, String>,
+    value: f64,
+    /// Milliseconds since the epoch, when the endpoint gives one
+    timestamp: Option<i64>,
+}
+
+impl Sample {
+    fn new(name: &str, value: f64) -> Self {
+        Self {
+            name: name.to_owned(),
+            labels: BTreeMap::new(),
+            value,
+            timestamp: None,
+        }
+    }
+}
+
+/// Samples of a scrape, with the types declared by `# TYPE` comments.
+#[derive(Debug, Default)]
+pub struct Exposition {
+    samples: Vec<Sample>,
+    types: HashMap<String, String>,
+}
+
+/// Type of a metric, looking at the family for histogram and summary series.
+fn metric_type<'a>(types: &'a HashMap<String, String>, name: &str) -> Option<&'a str> {
+    if let Some(metric_type) = types.get(name) {
+        return Some(metric_type);
+    }
+    ["_bucket", "_sum", "_count", "_total", "_created"]
+        .iter()
+        .filter_map(|suffix| name.strip_suffix(suffix))
+        .find_map(|family| types.get(family))
+        .map(String::as_str)
+}
+
+fn is_name_char(c: char, first: bool) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == ':' || (!first && c.is_ascii_digit())
+}
+
+fn parse_value(text: &str) -> Result<f64, String> {
+    match text {
+        "+Inf" => Ok(f64::INFINITY),
+        "-Inf" => Ok(f64::NEG_INFINITY),
+        "NaN" => Ok(f64::NAN),
+        _ => text
+            .parse()
+            .map_err(|_| format!("invalid sample value `{}`", text)),
+    }
+}
+
+/// Parse the `{name="value",...}` label set at the start of `text`,
+/// returning the labels and the rest of the line.
+fn parse_labels(text: &str) -> Result<(BTreeMap<String, String>, &str), String> {
+    let mut labels = BTreeMap::new();
+    let mut rest = text.trim_start();
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix('}') {
+            return Ok((labels, after));
+        }
+        let (name, after) = rest
+            .split_once('=')
+            .ok_or_else(|| "label without a value".to_owned())?;
+        let name = name.trim();
+        if name.is_empty()
+            || !name
+                .chars()
+                .enumerate()
+                .all(|(i, c)| is_name_char(c, i == 0))
+        {
+            return Err(format!("invalid label name `{}`", name));
+        }
+        let mut chars = after
+            .trim_start()
+            .strip_prefix('"')
+            .ok_or_else(|| format!("label {} value is not quoted", name))?
+            .char_indices();
+        let quoted = chars.as_str();
+        let mut value = String::new();
+        let end = loop {
+            match chars.next() {
+                Some((i, '"')) => break i,
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, c)) => value.push(c),
+                    None => return Err("unterminated label value".to_owned()),
+                },
+                Some((_, c)) => value.push(c),
+                None => return Err("unterminated label value".to_owned()),
+            }
+        };
+        labels.insert(name.to_owned(), value);
+        rest = quoted[end + 1..].trim_start();
+        rest = rest.strip_prefix(',').unwrap_or(rest);
+    }
+}
+
+/// Parse one sample line: `name{labels} value [timestamp]`.
+fn parse_sample(line: &str) -> Result<Sample, String> {
+    let name_end = line
+        .char_indices()
+        .find(|(i, c)| !is_name_char(*c, *i == 0))
+        .map_or(line.len(), |(i, _)| i);
+    if name_end == 0 {
+        return Err("missing metric name".to_owned());
+    }
+    let (name, rest) = line.split_at(name_end);
+    let (labels, rest) = match rest.strip_prefix('{') {
+        Some(rest) => parse_labels(rest)?,
+        None => (BTreeMap::new(), rest),
+    };
+    let mut fields = rest.split_whitespace();
+    let value = parse_value(fields.next().ok_or("missing sample value")?)?;
+    let timestamp = fields
+        .next()
+        .map(|ts| {
+            ts.parse::<i64>()
+                .map_err(|_| format!("invalid timestamp `{}`", ts))
+        })
+        .transpose()?;
+    if fields.next().is_some() {
+        return Err("unexpected text after the timestamp".to_owned());
+    }
+    Ok(Sample {
+        name: name.to_owned(),
+        labels,
+        value,
+        timestamp,
+    })
+}
+
+/// Parse the Prometheus text exposition format.
+/// Lines that cannot be read are returned as errors and skipped.
+pub fn parse_exposition(text: &str) -> (Exposition, Vec<String>) {
+    let mut exposition = Exposition::default();
+    let mut errors = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(comment) = line.strip_prefix('#') {
+            let mut words = comment.split_whitespace();
+            if let (Some("TYPE"), Some(name), Some(metric_type)) =
+                (words.next(), words.next(), words.next())
+            {
+                exposition
+                    .types
+                    .insert(name.to_owned(), metric_type.to_owned());
+            }
+            continue;
+        }
+        match parse_sample(line) {
+            Ok(sample) => exposition.samples.push(sample),
+            Err(err) => errors.push(format!("line {}: {}", number + 1, err)),
+        }
+    }
+    (exposition, errors)
+}
+
+/// JSON cannot hold NaN or infinities, which are kept as their Prometheus spelling.
+fn value_to_json(value: f64) -> serde_json::Value {
+    match serde_json::Number::from_f64(value) {
+        Some(number) => number.into(),
+        None if value.is_nan() => "NaN".into(),
+        None if value > 0.0 => "+Inf".into(),
+        None => "-Inf".into(),
+    }
+}
+
+/// Turn a sample into a log, stamped with its own timestamp or the scrape time.
+fn sample_log(
+    sample: Sample,
+    metric_type: Option<&str>,
+    target: &Target,
+    scrape_time: DateTime<Utc>,
+) -> serde_json::Value {
+    let time = sample
+        .timestamp
+        .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+        .unwrap_or(scrape_time);
+    let mut labels = target.labels.clone();
+    // Target labels win, as in Prometheus clashing exposed ones are kept
+    // prefixed with `exported_`
+    for (name, value) in sample.labels {
+        if labels.contains_key(&name) {
+            labels.insert(format!("exported_{}", name), value);
+        } else {
+            labels.insert(name, value);
+        }
+    }
+    let mut log = json!({
+        "time": time.to_rfc3339_opts(SecondsFormat::Millis, true),
+        "metric": sample.name,
+        "labels": labels,
+        "value": value_to_json(sample.value),
+        "target": target.url,
+    });
+    if let Some(metric_type) = metric_type {
+        log["type"] = metric_type.into();
+    }
+    log
+}
+
+async fn scrape(client: &Client, url: &str) -> Result<String, reqwest::Error> {
+    client
+        .get(url)
+        .header(ACCEPT, "text/plain;version=0.0.4")
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await
+}
+
+/// Scrape a target every `interval`, publishing its samples along with
+/// `up` and `scrape_duration_seconds`, as Prometheus does. Samples of a
+/// scrape that cannot be published are dropped, the next scrape retries.
+async fn scrape_target(
+    client: Client,
+    target: Target,
+    link: Arc<Mutex<Link>>,
+    interval: Duration,
+    samples_per_message: usize,
+) {
+    let args = {
+        let link = link.lock().await;
+        let amqp = link.amqp();
+        BasicPublishArguments::new(&amqp.exchange, &amqp.routing_key)
+    };
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        let scrape_time = Utc::now();
+        let started = Instant::now();
+        let (exposition, up) = match scrape(&client, &target.url).await {
+            Ok(body) => {
+                let (exposition, errors) = parse_exposition(&body);
+                for err in errors {
+                    warn!("skipped sample of {}: {}", target.url, err);
+                }
+                (exposition, 1.0)
+            }
+            Err(err) => {
+                warn!("scraping {} failed: {}", target.url, err);
+                (Exposition::default(), 0.0)
+            }
+        };
+        let duration = started.elapsed().as_secs_f64();
+        let mut logs: Vec<serde_json::Value> = Vec::with_capacity(exposition.samples.len() + 2);
+        for sample in [
+            Sample::new("up", up),
+            Sample::new("scrape_duration_seconds", duration),
+        ] {
+            logs.push(sample_log(sample, Some("gauge"), &target, scrape_time));
+        }
+        for sample in exposition.samples {
+            let metric_type = metric_type(&exposition.types, &sample.name);
+            logs.push(sample_log(sample, metric_type, &target, scrape_time));
+        }
+        let mut link = link.lock().await;
+        if !link.connected().await {
+            warn!("RabbitMQ is away, dropped a scrape of {}", target.url);
+            continue;
+        }
+        for chunk in logs.chunks(samples_per_message) {
+            let content = serde_json::to_vec(chunk).unwrap();
+            if let Err(err) = link
+                .publish(args.clone(), BasicProperties::default(), content)
+                .await
+            {
+                error!("publishing samples of {} failed: {}", target.url, err);
+                break;
+            }
+        }
+    }
+}
+
+#[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+async fn main() {
+    // construct a subscriber that prints formatted traces to stdout
+    // global subscriber with log level according to RUST_LOG
+    tracing_subscriber::registry()
+        .with(fmt::layer())
+        .with(
+            EnvFilter::builder()
+                .with_default_directive(metadata::LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
+        .try_init()
+        .ok();
+
+    let args = PromArgs::parse();
+    let print_config = args.print_config;
+    let config = match args.resolve() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("prom-producer: {}", err);
+            std::process::exit(2);
+        }
+    };
+    if print_config {
+        let shown = PromConfig {
+            amqp: config.amqp.redacted(),
+            ..config
+        };
+        print!("{}", toml::to_string(&shown).unwrap());
+        return;
+    }
+
+    let link = Arc::new(Mutex::new(Link::new(config.amqp.clone())));
+    let client = Client::builder()
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .build()
+        .unwrap();
+    for target in config.targets.iter() {
+        info!("scraping {} every {}s", target.url, config.interval_secs);
+        tokio::spawn(scrape_target(
+            client.clone(),
+            target.clone(),
+            link.clone(),
+            Duration::from_secs(config.interval_secs),
+            config.samples_per_message,
+        ));
+    }
+
+    shutdown_signal().await;
+    info!("stopping");
+    link.lock().await.close().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_exposition() {
+        let text = r#"
+# HELP http_requests_total The total number of HTTP requests.
+# TYPE http_requests_total counter
+http_requests_total{method="post",code="200"} 1027 1395066363000
+http_requests_total{method="post", code="400",} 3 1395066363000
+msdos_file_access_time_seconds{path="C:\\DIR\\FILE.TXT",error="Cannot find file:\n\"FILE.TXT\""} 1.458255915e9
+# TYPE rpc_duration_seconds histogram
+rpc_duration_seconds_bucket{le="+Inf"} 2693
+missing_value{a="b"}
+temperature NaN
+"#;
+        let (exposition, errors) = parse_exposition(text);
+        assert_eq!(errors, vec!["line 9: missing sample value".to_owned()]);
+        assert_eq!(exposition.samples.len(), 5);
+        let first = &exposition.samples[0];
+        assert_eq!(first.name, "http_requests_total");
+        assert_eq!(first.labels["code"], "200");
+        assert_eq!(first.value, 1027.0);
+        assert_eq!(first.timestamp, Some(1395066363000));
+        assert_eq!(exposition.samples[1].labels["code"], "400");
+        assert_eq!(
+            exposition.samples[2].labels["error"],
+            "Cannot find file:\n\"FILE.TXT\""
+        );
+        assert_eq!(exposition.samples[2].labels["path"], r"C:\DIR\FILE.TXT");
+        let types = &exposition.types;
+        assert_eq!(metric_type(types, "http_requests_total"), Some("counter"));
+        assert_eq!(
+            metric_type(types, "rpc_duration_seconds_bucket"),
+            Some("histogram")
+        );
+        assert_eq!(metric_type(types, "temperature"), None);
+        assert!(exposition.samples[4].value.is_nan());
+    }
+
+    #[test]
+    fn test_sample_log() {
+        let target = Target {
+            url: "http://node:9100/metrics".to_owned(),
+            labels: BTreeMap::from([
+                ("job".to_owned(), "node".to_owned()),
+                ("code".to_owned(), "target".to_owned()),
+            ]),
+        };
+        let sample = parse_sample(r#"requests{code="200"} +Inf 1395066363000"#).unwrap();
+        let log = sample_log(sample, Some("counter"), &target, Utc::now());
+        assert_eq!(
+            log,
+            json!({
+                "time": "2014-03-17T14:26:03.000Z",
+                "metric": "requests",
+                "labels": {"code": "target", "exported_code": "200", "job": "node"},
+                "value": "+Inf",
+                "target": "http://node:9100/metrics",
+                "type": "counter",
+            })
+        );
+        assert!(parse_sample("1abc 2").is_err());
+        assert!(parse_sample(r#"abc{a=b} 2"#).is_err());
+    }
+}
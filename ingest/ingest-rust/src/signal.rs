@@ -0,0 +1,17 @@
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::info;
+
+/// Resolve on the first SIGINT or SIGTERM, with the signal received.
+pub async fn shutdown_signal() -> SignalKind {
+    let mut terminate = signal(SignalKind::terminate()).expect("cannot listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {
+            info!("received SIGINT");
+            SignalKind::interrupt()
+        }
+        _ = terminate.recv() => {
+            info!("received SIGTERM");
+            SignalKind::terminate()
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    collections::VecDeque,
+    io,
+    path::{Path, PathBuf},
+};
+
+use tracing::warn;
+
+/// Batches kept on disk while they cannot be published, one file per batch
+/// named after its sequence number, so they are replayed in order.
+///
+/// The spool never grows past `max_bytes`: the oldest batches are dropped
+/// to make room for the new ones.
+pub struct Spool {
+    dir: PathBuf,
+    max_bytes: u64,
+    /// Sequence number and size of the spooled batches, oldest first
+    batches: VecDeque<(u64, u64)>,
+    bytes: u64,
+    next: u64,
+    /// Batches dropped because the spool was full
+    pub dropped: u64,
+}
+
+impl Spool {
+    /// Open the spool in `dir`, picking up the batches left by a previous run.
+    pub fn open(dir: &Path, max_bytes: u64) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let mut batches = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("batch") => {}
+                // Left by a crash while writing
+                Some("tmp") => {
+                    std::fs::remove_file(&path)?;
+                    continue;
+                }
+                _ => continue,
+            }
+            let Some(sequence) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            batches.push((sequence, std::fs::metadata(&path)?.len()));
+        }
+        batches.sort_unstable();
+        let mut spool = Self {
+            dir: dir.to_owned(),
+            max_bytes,
+            bytes: batches.iter().map(|(_, size)| size).sum(),
+            next: batches.last().map_or(0, |(sequence, _)| sequence + 1),
+            batches: batches.into(),
+            dropped: 0,
+        };
+        spool.enforce_cap()?;
+        Ok(spool)
+    }
+
+    fn batch_path(&self, sequence: u64) -> PathBuf {
+        self.dir.join(format!("{:020}.batch", sequence))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.batches.len()
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Append a batch, dropping the oldest ones if the spool gets too big.
+    pub fn push(&mut self, content: &[u8]) -> io::Result<()> {
+        let sequence = self.next;
+        let path = self.batch_path(sequence);
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, &path)?;
+        self.next += 1;
+        self.batches.push_back((sequence, content.len() as u64));
+        self.bytes += content.len() as u64;
+        self.enforce_cap()
+    }
+
+    fn enforce_cap(&mut self) -> io::Result<()> {
+        while self.bytes > self.max_bytes && !self.batches.is_empty() {
+            self.pop_front()?;
+            self.dropped += 1;
+            warn!(
+                "spool is over {} bytes, dropped its oldest batch",
+                self.max_bytes
+            );
+        }
+        Ok(())
+    }
+
+    /// Content of the oldest batch.
+    pub fn front(&self) -> io::Result<Option<Vec<u8>>> {
+        match self.batches.front() {
+            Some((sequence, _)) => std::fs::read(self.batch_path(*sequence)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Remove the oldest batch, once it is published.
+    pub fn pop_front(&mut self) -> io::Result<()> {
+        if let Some((sequence, size)) = self.batches.pop_front() {
+            self.bytes -= size;
+            std::fs::remove_file(self.batch_path(sequence))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spool() {
+        let dir = std::env::temp_dir().join(format!("logdog-spool-{}", std::process::id()));
+        let mut spool = Spool::open(&dir, 10).unwrap();
+        assert!(spool.is_empty());
+        spool.push(b"[1,2]").unwrap();
+        spool.push(b"[3]").unwrap();
+        assert_eq!(spool.front().unwrap(), Some(b"[1,2]".to_vec()));
+        // Over 10 bytes, the oldest batch goes
+        spool.push(b"[4,5]").unwrap();
+        assert_eq!((spool.len(), spool.bytes(), spool.dropped), (2, 8, 1));
+        assert_eq!(spool.front().unwrap(), Some(b"[3]".to_vec()));
+
+        // A restart replays the same batches, in order
+        let mut spool = Spool::open(&dir, 10).unwrap();
+        assert_eq!(spool.len(), 2);
+        assert_eq!(spool.front().unwrap(), Some(b"[3]".to_vec()));
+        spool.pop_front().unwrap();
+        spool.push(b"[6]").unwrap();
+        assert_eq!(spool.front().unwrap(), Some(b"[4,5]".to_vec()));
+        spool.pop_front().unwrap();
+        assert_eq!(spool.front().unwrap(), Some(b"[6]".to_vec()));
+        spool.pop_front().unwrap();
+        assert!(spool.is_empty());
+        assert_eq!(spool.front().unwrap(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
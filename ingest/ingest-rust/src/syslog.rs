@@ -0,0 +1,494 @@
+use std::{
+    io::{self, BufRead, BufReader, Read},
+    net::{TcpListener, UdpSocket},
+    os::unix::net::UnixDatagram,
+    path::Path,
+};
+
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use tracing::{info, warn};
+
+/// Longest message accepted, over UDP and in an octet-counted TCP frame.
+const MAX_MESSAGE: usize = 64 * 1024;
+
+const FACILITIES: [&str; 24] = [
+    "kern",
+    "user",
+    "mail",
+    "daemon",
+    "auth",
+    "syslog",
+    "lpr",
+    "news",
+    "uucp",
+    "cron",
+    "authpriv",
+    "ftp",
+    "ntp",
+    "security",
+    "console",
+    "solaris-cron",
+    "local0",
+    "local1",
+    "local2",
+    "local3",
+    "local4",
+    "local5",
+    "local6",
+    "local7",
+];
+
+const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+/// Level of a syslog severity, spelled as logdog levels are.
+fn severity_level(severity: usize) -> &'static str {
+    match severity {
+        0..=2 => "CRITICAL",
+        3 => "ERROR",
+        4 => "WARNING",
+        5 | 6 => "INFO",
+        _ => "DEBUG",
+    }
+}
+
+/// Split the `<PRI>` header, defaulting to user.notice when there is none.
+fn parse_pri(message: &str) -> (usize, &str) {
+    let default = (13, message);
+    let Some(rest) = message.strip_prefix('<') else {
+        return default;
+    };
+    let Some((pri, rest)) = rest.split_once('>') else {
+        return default;
+    };
+    if pri.is_empty() || pri.len() > 3 || !pri.bytes().all(|b| b.is_ascii_digit()) {
+        return default;
+    }
+    match pri.parse::<usize>() {
+        Ok(pri) if pri < 192 => (pri, rest),
+        _ => default,
+    }
+}
+
+/// Split the next space separated field, `None` for the `-` nil value.
+fn next_field(text: &str) -> (Option<&str>, &str) {
+    let (field, rest) = text.split_once(' ').unwrap_or((text, ""));
+    ((field != "-").then_some(field), rest)
+}
+
+/// Parse RFC 5424 structured data: `[id param="value" ...][id2 ...]`.
+fn parse_structured_data(text: &str) -> Option<(Map<String, Value>, &str)> {
+    let mut elements = Map::new();
+    let mut rest = text;
+    while let Some(element) = rest.strip_prefix('[') {
+        let end = element.find([' ', ']'])?;
+        let id = &element[..end];
+        let mut params = Map::new();
+        rest = &element[end..];
+        loop {
+            rest = rest.trim_start_matches(' ');
+            if let Some(after) = rest.strip_prefix(']') {
+                rest = after;
+                break;
+            }
+            let (name, after) = rest.split_once("=\"")?;
+            let mut value = String::new();
+            let mut chars = after.char_indices();
+            let close = loop {
+                match chars.next()? {
+                    (i, '"') => break i,
+                    (_, '\\') => {
+                        let (_, escaped) = chars.next()?;
+                        if !matches!(escaped, '"' | '\\' | ']') {
+                            value.push('\\');
+                        }
+                        value.push(escaped);
+                    }
+                    (_, c) => value.push(c),
+                }
+            };
+            params.insert(name.to_owned(), value.into());
+            rest = &after[close + 1..];
+        }
+        elements.insert(id.to_owned(), params.into());
+    }
+    Some((elements, rest))
+}
+
+/// Fields of an RFC 5424 message, after `<PRI>1 `.
+fn parse_rfc5424(text: &str, log: &mut Map<String, Value>) -> Option<String> {
+    let (timestamp, rest) = next_field(text);
+    let (hostname, rest) = next_field(rest);
+    let (appname, rest) = next_field(rest);
+    let (procid, rest) = next_field(rest);
+    let (msgid, rest) = next_field(rest);
+    let rest = match rest.strip_prefix('-') {
+        Some(rest) => rest,
+        None => {
+            let (structured_data, rest) = parse_structured_data(rest)?;
+            log.insert("structured_data".to_owned(), structured_data.into());
+            rest
+        }
+    };
+    if let Some(time) = timestamp.and_then(|ts| DateTime::parse_from_rfc3339(ts).ok()) {
+        let time = time.with_timezone(&Utc);
+        log.insert("time".to_owned(), format_time(time).into());
+    }
+    for (key, value) in [
+        ("source", hostname),
+        ("appname", appname),
+        ("procid", procid),
+        ("msgid", msgid),
+    ] {
+        if let Some(value) = value {
+            log.insert(key.to_owned(), value.into());
+        }
+    }
+    let message = rest.strip_prefix(' ').unwrap_or(rest);
+    Some(message.trim_start_matches('\u{feff}').to_owned())
+}
+
+/// Fields of an RFC 3164 message: `Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG`.
+/// Devices often leave the timestamp and hostname out, so both are optional,
+/// as does `syslog(3)` on `/dev/log`: a token ending with `:` or holding a
+/// `[` after the timestamp is the tag.
+fn parse_rfc3164(text: &str, now: DateTime<Utc>, log: &mut Map<String, Value>) -> String {
+    let mut rest = text;
+    if let Some(time) = rest.get(..15).and_then(|ts| parse_bsd_time(ts, now)) {
+        log.insert("time".to_owned(), format_time(time).into());
+        rest = rest[15..].trim_start_matches(' ');
+        if let Some((hostname, after)) = rest.split_once(' ') {
+            if !hostname.ends_with(':') && !hostname.contains('[') {
+                log.insert("source".to_owned(), hostname.into());
+                rest = after;
+            }
+        }
+    }
+    let tag_end = rest.find([':', '[', ' ']).unwrap_or(0);
+    let (tag, after) = rest.split_at(tag_end);
+    let (procid, after) = match after.strip_prefix('[').and_then(|a| a.split_once(']')) {
+        Some((procid, after)) => (Some(procid), after),
+        None => (None, after),
+    };
+    match after.strip_prefix(':') {
+        Some(message) if !tag.is_empty() => {
+            log.insert("appname".to_owned(), tag.into());
+            if let Some(procid) = procid {
+                log.insert("procid".to_owned(), procid.into());
+            }
+            message.trim_start_matches(' ').to_owned()
+        }
+        _ => rest.to_owned(),
+    }
+}
+
+/// Read a BSD timestamp, which has no year: the current one is assumed, or
+/// the previous one when that would put the message in the future.
+fn parse_bsd_time(text: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let with_year = |year: i32| {
+        NaiveDateTime::parse_from_str(&format!("{} {}", year, text), "%Y %b %e %H:%M:%S")
+            .ok()
+            .map(|time| time.and_utc())
+    };
+    let time = with_year(now.year())?;
+    if time > now + Duration::days(1) {
+        return with_year(now.year() - 1);
+    }
+    Some(time)
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+/// Turn an RFC 5424 or RFC 3164 message into a log. The peer address is the
+/// `source` when the message has no hostname.
+pub fn parse_syslog(message: &[u8], peer: Option<&str>, now: DateTime<Utc>) -> Value {
+    let message = String::from_utf8_lossy(message);
+    let message = message.trim_end_matches(['\n', '\r', '\0']);
+    let (pri, rest) = parse_pri(message);
+    let (facility, severity) = (pri / 8, pri % 8);
+    let mut log = Map::new();
+    let text = match rest.strip_prefix("1 ") {
+        Some(rfc5424) => match parse_rfc5424(rfc5424, &mut log) {
+            Some(text) => text,
+            None => {
+                log.clear();
+                parse_rfc3164(rest, now, &mut log)
+            }
+        },
+        None => parse_rfc3164(rest, now, &mut log),
+    };
+    log.insert("level".to_owned(), severity_level(severity).into());
+    log.insert("facility".to_owned(), FACILITIES[facility].into());
+    log.insert("severity".to_owned(), SEVERITIES[severity].into());
+    if let Some(peer) = peer {
+        log.entry("source").or_insert(peer.into());
+    }
+    log.insert("message".to_owned(), text.into());
+    log.into()
+}
+
+/// Read the next message of a TCP stream, framed by octet counting
+/// (`<length> <message>`) or ended by a newline (RFC 6587).
+///
+/// Neither frame is read past `MAX_MESSAGE`: a bad octet count fails, as the
+/// stream cannot be resynchronized, while a longer line is skipped.
+fn read_frame(reader: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    loop {
+        let first = match reader.fill_buf()?.first() {
+            Some(first) => *first,
+            None => return Ok(None),
+        };
+        let mut frame = Vec::new();
+        if first.is_ascii_digit() {
+            // Room for the digits of MAX_MESSAGE and the space
+            reader.by_ref().take(8).read_until(b' ', &mut frame)?;
+            let length = std::str::from_utf8(&frame)
+                .ok()
+                .and_then(|length| length.strip_suffix(' '))
+                .and_then(|length| length.parse::<usize>().ok())
+                .filter(|length| *length <= MAX_MESSAGE)
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "invalid frame length")
+                })?;
+            frame = vec![0; length];
+            reader.read_exact(&mut frame)?;
+            return Ok(Some(frame));
+        }
+        reader
+            .by_ref()
+            .take(MAX_MESSAGE as u64 + 1)
+            .read_until(b'\n', &mut frame)?;
+        if frame.len() <= MAX_MESSAGE || frame.ends_with(b"\n") {
+            return Ok(Some(frame));
+        }
+        warn!("syslog tcp frame longer than {} bytes skipped", MAX_MESSAGE);
+        skip_line(reader)?;
+    }
+}
+
+/// Discard the stream up to the next newline, without buffering it.
+fn skip_line(reader: &mut impl BufRead) -> io::Result<()> {
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        match buf.iter().position(|byte| *byte == b'\n') {
+            Some(end) => {
+                reader.consume(end + 1);
+                return Ok(());
+            }
+            None => {
+                let read = buf.len();
+                reader.consume(read);
+            }
+        }
+    }
+}
+
+/// Receive syslog datagrams on `addr`.
+pub fn listen_udp<F>(addr: &str, emit: F) -> io::Result<()>
+where
+    F: Fn(Value) + Send + 'static,
+{
+    let socket = UdpSocket::bind(addr)?;
+    info!("receiving syslog on udp {}", addr);
+    std::thread::spawn(move || {
+        let mut buf = vec![0; MAX_MESSAGE];
+        loop {
+            match socket.recv_from(&mut buf) {
+                Ok((read, peer)) => {
+                    let peer = peer.ip().to_string();
+                    emit(parse_syslog(&buf[..read], Some(&peer), Utc::now()));
+                }
+                Err(err) => warn!("syslog udp receive failed: {}", err),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Accept syslog streams on `addr`, each connection read by its own thread.
+pub fn listen_tcp<F>(addr: &str, emit: F) -> io::Result<()>
+where
+    F: Fn(Value) + Clone + Send + 'static,
+{
+    let listener = TcpListener::bind(addr)?;
+    info!("receiving syslog on tcp {}", addr);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("syslog tcp accept failed: {}", err);
+                    continue;
+                }
+            };
+            let peer = stream.peer_addr().map(|peer| peer.ip().to_string()).ok();
+            let emit = emit.clone();
+            std::thread::spawn(move || {
+                let mut reader = BufReader::new(stream);
+                loop {
+                    match read_frame(&mut reader) {
+                        Ok(Some(frame)) => emit(parse_syslog(&frame, peer.as_deref(), Utc::now())),
+                        Ok(None) => break,
+                        Err(err) => {
+                            warn!("syslog tcp connection dropped: {}", err);
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Receive syslog datagrams on a Unix socket, as `/dev/log` does, replacing
+/// a socket file left by a previous run.
+pub fn listen_unix<F>(path: &Path, emit: F) -> io::Result<()>
+where
+    F: Fn(Value) + Send + 'static,
+{
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    let socket = UnixDatagram::bind(path)?;
+    info!("receiving syslog on unix socket {}", path.display());
+    std::thread::spawn(move || {
+        let mut buf = vec![0; MAX_MESSAGE];
+        loop {
+            match socket.recv(&mut buf) {
+                Ok(read) => emit(parse_syslog(&buf[..read], None, Utc::now())),
+                Err(err) => warn!("syslog unix receive failed: {}", err),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_rfc5424() {
+        let now = Utc::now();
+        let message = "<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 \
+            [exampleSDID@32473 iut=\"3\" eventSource=\"App\\]\"][meta x=\"a\\\"b\"] \u{feff}An application event\n";
+        assert_eq!(
+            parse_syslog(message.as_bytes(), Some("10.0.0.1"), now),
+            json!({
+                "time": "2003-10-11T22:14:15.003Z",
+                "level": "INFO",
+                "facility": "local4",
+                "severity": "notice",
+                "source": "mymachine.example.com",
+                "appname": "evntslog",
+                "msgid": "ID47",
+                "structured_data": {
+                    "exampleSDID@32473": {"iut": "3", "eventSource": "App]"},
+                    "meta": {"x": "a\"b"},
+                },
+                "message": "An application event",
+            })
+        );
+        assert_eq!(
+            parse_syslog(b"<11>1 - - - 42 - -", None, now),
+            json!({
+                "level": "ERROR",
+                "facility": "user",
+                "severity": "err",
+                "procid": "42",
+                "message": "",
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_rfc3164() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap();
+        assert_eq!(
+            parse_syslog(
+                b"<34>Oct 11 22:14:15 mymachine su[123]: 'su root' failed",
+                Some("10.0.0.1"),
+                now
+            ),
+            json!({
+                "time": "2023-10-11T22:14:15Z",
+                "level": "CRITICAL",
+                "facility": "auth",
+                "severity": "crit",
+                "source": "mymachine",
+                "appname": "su",
+                "procid": "123",
+                "message": "'su root' failed",
+            })
+        );
+        assert_eq!(
+            parse_syslog(b"<13>Oct 18 12:00:00 sshd[42]: Accepted key", None, now),
+            json!({
+                "time": "2023-10-18T12:00:00Z",
+                "level": "INFO",
+                "facility": "user",
+                "severity": "notice",
+                "appname": "sshd",
+                "procid": "42",
+                "message": "Accepted key",
+            })
+        );
+        assert_eq!(
+            parse_syslog(b"<13>Oct 18 12:00:00 cron: job done", None, now)["appname"],
+            json!("cron")
+        );
+        assert_eq!(
+            parse_syslog(b"<190>link down on port 3", Some("10.0.0.1"), now),
+            json!({
+                "level": "INFO",
+                "facility": "local7",
+                "severity": "info",
+                "source": "10.0.0.1",
+                "message": "link down on port 3",
+            })
+        );
+        assert_eq!(
+            parse_syslog(b"no priority", None, now)["severity"],
+            json!("notice")
+        );
+    }
+
+    #[test]
+    fn test_read_frame() {
+        let mut reader = io::Cursor::new(b"12 <13>hi\nthere<13>line\n<14>last".to_vec());
+        assert_eq!(
+            read_frame(&mut reader).unwrap(),
+            Some(b"<13>hi\nthere".to_vec())
+        );
+        assert_eq!(
+            read_frame(&mut reader).unwrap(),
+            Some(b"<13>line\n".to_vec())
+        );
+        assert_eq!(read_frame(&mut reader).unwrap(), Some(b"<14>last".to_vec()));
+        assert_eq!(read_frame(&mut reader).unwrap(), None);
+        let mut reader = io::Cursor::new(b"99999999 <13>".to_vec());
+        assert!(read_frame(&mut reader).is_err());
+        let mut reader = io::Cursor::new([b'1'; MAX_MESSAGE * 2].to_vec());
+        assert!(read_frame(&mut reader).is_err());
+
+        let mut long = vec![b'a'; MAX_MESSAGE * 3];
+        long.extend(b"\n<13>next\n");
+        let mut reader = io::Cursor::new(long);
+        assert_eq!(
+            read_frame(&mut reader).unwrap(),
+            Some(b"<13>next\n".to_vec())
+        );
+        assert_eq!(read_frame(&mut reader).unwrap(), None);
+    }
+}
@@ -0,0 +1,465 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{File, Metadata},
+    io::{self, Read, Seek, SeekFrom},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+/// How long a rotated file is still read, for writers that have not reopened
+/// the new file yet.
+const ROTATED_GRACE: Duration = Duration::from_secs(5);
+
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Device and inode of a file, which a rename keeps.
+pub type FileId = (u64, u64);
+
+fn file_id(metadata: &Metadata) -> FileId {
+    (metadata.dev(), metadata.ino())
+}
+
+/// Position in a file, after the last line published.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FileOffset {
+    pub dev: u64,
+    pub inode: u64,
+    pub offset: u64,
+}
+
+impl FileOffset {
+    pub fn id(&self) -> FileId {
+        (self.dev, self.inode)
+    }
+}
+
+/// Offsets saved before files were followed by inode, keyed by path and
+/// without the device.
+#[derive(Deserialize)]
+struct PathOffset {
+    inode: u64,
+    offset: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SavedCheckpoint {
+    Files(Vec<FileOffset>),
+    ByPath(BTreeMap<String, PathOffset>),
+}
+
+/// Offsets of the tailed files by device and inode, saved so a restart
+/// resumes where it stopped, even for files renamed meanwhile.
+#[derive(Debug, Default)]
+pub struct Checkpoint {
+    path: PathBuf,
+    pub files: BTreeMap<FileId, FileOffset>,
+}
+
+impl Checkpoint {
+    /// Read the checkpoint at `path`, or start an empty one if there is none.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let saved = match std::fs::read(path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .map_err(|err| format!("invalid checkpoint {}: {}", path.display(), err))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => SavedCheckpoint::Files(vec![]),
+            Err(err) => return Err(format!("cannot read {}: {}", path.display(), err)),
+        };
+        let files = match saved {
+            SavedCheckpoint::Files(files) => files,
+            // Offsets of files still at their path, the others are unknown
+            SavedCheckpoint::ByPath(files) => files
+                .into_iter()
+                .filter_map(|(file, saved)| {
+                    let metadata = std::fs::metadata(file).ok()?;
+                    (metadata.ino() == saved.inode).then_some(FileOffset {
+                        dev: metadata.dev(),
+                        inode: saved.inode,
+                        offset: saved.offset,
+                    })
+                })
+                .collect(),
+        };
+        Ok(Self {
+            path: path.to_owned(),
+            files: files.into_iter().map(|file| (file.id(), file)).collect(),
+        })
+    }
+
+    /// Write the checkpoint to a temporary file renamed over the previous one,
+    /// so a crash never leaves it half written.
+    pub fn save(&self) -> io::Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let files: Vec<&FileOffset> = self.files.values().collect();
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&files)?)?;
+        std::fs::rename(&tmp, &self.path)
+    }
+}
+
+/// A complete line of a tailed file.
+#[derive(Debug)]
+pub struct TailLine {
+    pub text: String,
+    pub path: Arc<str>,
+    /// Where the file is read up to once this line is published, `None` for
+    /// the leftovers of a rotated file.
+    pub position: Option<FileOffset>,
+}
+
+struct TailedFile {
+    path: Arc<str>,
+    file: File,
+    id: FileId,
+    /// End of the last complete line read
+    offset: u64,
+    partial: Vec<u8>,
+}
+
+impl TailedFile {
+    fn open(path: &str, start: Option<FileOffset>) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let id = file_id(&metadata);
+        let offset = match start {
+            Some(start) if start.id() == id && start.offset <= metadata.len() => start.offset,
+            _ => 0,
+        };
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Self {
+            path: path.into(),
+            file,
+            id,
+            offset,
+            partial: Vec::new(),
+        })
+    }
+
+    /// Read the complete lines appended since the last call.
+    fn read_lines(&mut self, rotated: bool, emit: &mut impl FnMut(TailLine)) -> io::Result<()> {
+        let mut chunk = vec![0; READ_CHUNK];
+        loop {
+            let read = self.file.read(&mut chunk)?;
+            if read == 0 {
+                return Ok(());
+            }
+            self.partial.extend_from_slice(&chunk[..read]);
+            let mut start = 0;
+            while let Some(end) = self.partial[start..].iter().position(|b| *b == b'\n') {
+                let line = &self.partial[start..start + end];
+                self.offset += end as u64 + 1;
+                start += end + 1;
+                emit(TailLine {
+                    text: String::from_utf8_lossy(line).into_owned(),
+                    path: self.path.clone(),
+                    position: (!rotated).then_some(FileOffset {
+                        dev: self.id.0,
+                        inode: self.id.1,
+                        offset: self.offset,
+                    }),
+                });
+            }
+            self.partial.drain(..start);
+        }
+    }
+
+    /// Emit the last line of a file that will not be read anymore, even
+    /// without its newline.
+    fn flush(mut self, emit: &mut impl FnMut(TailLine)) {
+        if let Err(err) = self.read_lines(true, emit) {
+            warn!("reading {} failed: {}", self.path, err);
+        }
+        if !self.partial.is_empty() {
+            emit(TailLine {
+                text: String::from_utf8_lossy(&self.partial).into_owned(),
+                path: self.path.clone(),
+                position: None,
+            });
+        }
+    }
+}
+
+/// Follow the files matching glob patterns, through rename and copytruncate
+/// rotations.
+///
+/// Files are polled: each call to [`FileTailer::poll`] reads what was
+/// appended, looks for rotated or truncated files and for new matches.
+/// Files are told apart by device and inode, so a rotated file renamed to a
+/// path that still matches is followed on rather than read again.
+pub struct FileTailer {
+    patterns: Vec<glob::Pattern>,
+    files: HashMap<FileId, TailedFile>,
+    rotated: Vec<(TailedFile, Instant)>,
+    /// Offsets to resume from, only used for the files found by the first poll
+    start: BTreeMap<FileId, FileOffset>,
+}
+
+impl FileTailer {
+    pub fn new(patterns: &[String], start: BTreeMap<FileId, FileOffset>) -> Result<Self, String> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                glob::Pattern::new(pattern)
+                    .map_err(|err| format!("invalid file pattern {}: {}", pattern, err))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            patterns,
+            files: HashMap::new(),
+            rotated: Vec::new(),
+            start,
+        })
+    }
+
+    fn matching_paths(&self) -> Vec<String> {
+        let mut paths = Vec::new();
+        for pattern in self.patterns.iter() {
+            let Ok(matches) = glob::glob(pattern.as_str()) else {
+                continue;
+            };
+            for path in matches.flatten() {
+                if path.is_file() {
+                    paths.push(path.to_string_lossy().into_owned());
+                }
+            }
+        }
+        paths
+    }
+
+    /// Files matching the patterns now, whose offsets are worth keeping.
+    pub fn matching_files(&self) -> HashSet<FileId> {
+        self.matching_paths()
+            .into_iter()
+            .filter_map(|path| std::fs::metadata(path).ok())
+            .map(|metadata| file_id(&metadata))
+            .collect()
+    }
+
+    /// Read the lines appended to the tailed files, then pick up rotations
+    /// and new files.
+    pub fn poll(&mut self, emit: &mut impl FnMut(TailLine)) {
+        let now = Instant::now();
+        for (file, _) in self.rotated.iter_mut() {
+            if let Err(err) = file.read_lines(true, emit) {
+                warn!("reading {} failed: {}", file.path, err);
+            }
+        }
+        let (expired, rotated) = std::mem::take(&mut self.rotated)
+            .into_iter()
+            .partition(|(_, deadline)| *deadline <= now);
+        self.rotated = rotated;
+        for (file, _) in expired {
+            file.flush(emit);
+        }
+
+        let mut gone = Vec::new();
+        for file in self.files.values_mut() {
+            if let Err(err) = file.read_lines(false, emit) {
+                warn!("reading {} failed: {}", file.path, err);
+            }
+            match std::fs::metadata(&*file.path) {
+                Ok(metadata) if file_id(&metadata) != file.id => gone.push(file.id),
+                Ok(metadata) if metadata.len() < file.offset + file.partial.len() as u64 => {
+                    info!("{} was truncated, reading it from the start", file.path);
+                    if let Err(err) = file.file.seek(SeekFrom::Start(0)) {
+                        warn!("rewinding {} failed: {}", file.path, err);
+                    }
+                    file.offset = 0;
+                    file.partial.clear();
+                    if let Err(err) = file.read_lines(false, emit) {
+                        warn!("reading {} failed: {}", file.path, err);
+                    }
+                }
+                Ok(_) => {}
+                Err(_) => gone.push(file.id),
+            }
+        }
+        for id in gone {
+            if let Some(file) = self.files.remove(&id) {
+                info!("{} was rotated or removed", file.path);
+                self.rotated.push((file, now + ROTATED_GRACE));
+            }
+        }
+
+        for path in self.matching_paths() {
+            let Ok(metadata) = std::fs::metadata(&path) else {
+                continue;
+            };
+            let id = file_id(&metadata);
+            if self.files.contains_key(&id) {
+                continue;
+            }
+            let file = match self.rotated.iter().position(|(file, _)| file.id == id) {
+                Some(index) => {
+                    let (mut file, _) = self.rotated.remove(index);
+                    info!("following {} renamed to {}", file.path, path);
+                    file.path = path.as_str().into();
+                    Ok(file)
+                }
+                None => TailedFile::open(&path, self.start.get(&id).copied()).inspect(|file| {
+                    info!("tailing {} from offset {}", path, file.offset);
+                }),
+            };
+            match file {
+                Ok(mut file) if !self.files.contains_key(&file.id) => {
+                    if let Err(err) = file.read_lines(false, emit) {
+                        warn!("reading {} failed: {}", path, err);
+                    }
+                    self.files.insert(file.id, file);
+                }
+                Ok(_) => {}
+                Err(err) => warn!("opening {} failed: {}", path, err),
+            }
+        }
+        self.start.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn poll(tailer: &mut FileTailer) -> Vec<(String, Option<u64>)> {
+        let mut lines = Vec::new();
+        tailer.poll(&mut |line: TailLine| {
+            lines.push((line.text, line.position.map(|position| position.offset)))
+        });
+        lines
+    }
+
+    #[test]
+    fn test_file_tailer() {
+        let dir = std::env::temp_dir().join(format!("logdog-tail-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        std::fs::write(&path, "one\ntwo\nthr").unwrap();
+        let (dev, inode) = file_id(&std::fs::metadata(&path).unwrap());
+        let pattern = dir.join("*.log").to_string_lossy().into_owned();
+
+        // Resume after `one`, keeping the partial line for later
+        let start = FileOffset {
+            dev,
+            inode,
+            offset: 4,
+        };
+        let start = BTreeMap::from([(start.id(), start)]);
+        let mut tailer = FileTailer::new(&[pattern], start).unwrap();
+        assert_eq!(poll(&mut tailer), vec![("two".to_owned(), Some(8))]);
+
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"ee\n").unwrap();
+        assert_eq!(poll(&mut tailer), vec![("three".to_owned(), Some(14))]);
+
+        // copytruncate
+        file.set_len(0).unwrap();
+        file.write_all(b"four\n").unwrap();
+        assert_eq!(poll(&mut tailer), vec![("four".to_owned(), Some(5))]);
+
+        // rename, then the writer keeps going on the old file for a while
+        std::fs::rename(&path, dir.join("app.log.1")).unwrap();
+        std::fs::write(&path, "six\n").unwrap();
+        file.write_all(b"five\n").unwrap();
+        assert_eq!(
+            poll(&mut tailer),
+            vec![("five".to_owned(), Some(10)), ("six".to_owned(), Some(4))]
+        );
+        // Leftovers of the rotated file are not checkpointed
+        file.write_all(b"seven").unwrap();
+        assert_eq!(poll(&mut tailer), vec![]);
+        tailer.rotated[0].1 = Instant::now();
+        assert_eq!(poll(&mut tailer), vec![("seven".to_owned(), None)]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rename_to_matching_path() {
+        let dir = std::env::temp_dir().join(format!("logdog-rename-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        std::fs::write(&path, "one\ntwo\n").unwrap();
+        let pattern = dir.join("app.log*").to_string_lossy().into_owned();
+        let mut tailer = FileTailer::new(std::slice::from_ref(&pattern), BTreeMap::new()).unwrap();
+        assert_eq!(
+            poll(&mut tailer),
+            vec![("one".to_owned(), Some(4)), ("two".to_owned(), Some(8))]
+        );
+
+        // logrotate renames app.log to app.log.1, which the pattern matches
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        std::fs::rename(&path, dir.join("app.log.1")).unwrap();
+        std::fs::write(&path, "four\n").unwrap();
+        file.write_all(b"three\n").unwrap();
+        let mut lines = poll(&mut tailer);
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec![("four".to_owned(), Some(5)), ("three".to_owned(), Some(14))]
+        );
+        assert!(tailer.rotated.is_empty());
+        assert_eq!(poll(&mut tailer), vec![]);
+
+        // A restart resumes both files by inode
+        let start: BTreeMap<FileId, FileOffset> = tailer
+            .files
+            .values()
+            .map(|file| {
+                let offset = FileOffset {
+                    dev: file.id.0,
+                    inode: file.id.1,
+                    offset: file.offset,
+                };
+                (file.id, offset)
+            })
+            .collect();
+        let mut tailer = FileTailer::new(&[pattern], start).unwrap();
+        assert_eq!(tailer.matching_files().len(), 2);
+        assert_eq!(poll(&mut tailer), vec![]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let path = std::env::temp_dir().join(format!("logdog-ckpt-{}.json", std::process::id()));
+        let mut checkpoint = Checkpoint::load(&path).unwrap();
+        assert!(checkpoint.files.is_empty());
+        let offset = FileOffset {
+            dev: 2,
+            inode: 3,
+            offset: 12,
+        };
+        checkpoint.files.insert(offset.id(), offset);
+        checkpoint.save().unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        assert_eq!(loaded.files[&(2, 3)], offset);
+
+        // Checkpoints keyed by path keep the offsets of files still there
+        let (dev, inode) = file_id(&std::fs::metadata(&path).unwrap());
+        let legacy = serde_json::json!({
+            path.to_string_lossy(): {"inode": inode, "offset": 1},
+            "/nonexistent/b.log": {"inode": 4, "offset": 2},
+        });
+        std::fs::write(&path, legacy.to_string()).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        assert_eq!(
+            loaded.files.into_values().collect::<Vec<_>>(),
+            vec![FileOffset {
+                dev,
+                inode,
+                offset: 1
+            }]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
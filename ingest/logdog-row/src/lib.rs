@@ -0,0 +1,237 @@
+//! Rows of the `logs` table, built alike by logdog-consumer from RabbitMQ
+//! messages and by logsearcher-server from the logs posted to it.
+
+use std::{collections::HashSet, sync::OnceLock};
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use regex::Regex;
+
+/// Fields holding the event time by default, the first that parses wins.
+pub const DEFAULT_TIME_FIELDS: [&str; 4] = ["time", "timestamp", "@timestamp", "ts"];
+
+/// Formats without an offset are read as UTC.
+const NAIVE_TIME_FORMATS: [&str; 4] = [
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y/%m/%d %H:%M:%S%.f",
+    "%d/%m/%Y %H:%M:%S%.f",
+];
+
+const OFFSET_TIME_FORMATS: [&str; 2] = ["%d/%b/%Y:%H:%M:%S %z", "%Y-%m-%d %H:%M:%S%.f %z"];
+
+/// Epoch timestamps are only read between 2000-01-01 and 2100-01-01, in seconds.
+const EPOCH_RANGE: std::ops::Range<f64> = 946_684_800.0..4_102_444_800.0;
+
+/// Read an epoch timestamp in seconds, millis, micros or nanos, the unit that
+/// puts it in [`EPOCH_RANGE`]. Other values are out of range.
+fn parse_epoch(value: f64) -> Result<DateTime<Utc>, String> {
+    for nanos_per_unit in [1e9, 1e6, 1e3, 1.0] {
+        if EPOCH_RANGE.contains(&(value * nanos_per_unit / 1e9)) {
+            return Ok(Utc.timestamp_nanos((value * nanos_per_unit) as i64));
+        }
+    }
+    Err(format!(
+        "epoch {} is not in seconds, millis, micros or nanos between 2000 and 2100",
+        value
+    ))
+}
+
+/// Parse a log timestamp: RFC 3339, RFC 2822, epoch numbers or a few common layouts.
+/// Values that are not timestamps are `None`, and epochs out of range an error.
+pub fn parse_timestamp(value: &serde_json::Value) -> Result<Option<DateTime<Utc>>, String> {
+    if let Some(num) = value.as_i64() {
+        return parse_epoch(num as f64).map(Some);
+    }
+    if let Some(num) = value.as_f64() {
+        return parse_epoch(num).map(Some);
+    }
+    let Some(text) = value.as_str().map(str::trim) else {
+        return Ok(None);
+    };
+    if let Ok(num) = text.parse::<f64>() {
+        return parse_epoch(num).map(Some);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(Some(time.with_timezone(&Utc)));
+    }
+    if let Ok(time) = DateTime::parse_from_rfc2822(text) {
+        return Ok(Some(time.with_timezone(&Utc)));
+    }
+    for format in OFFSET_TIME_FORMATS {
+        if let Ok(time) = DateTime::parse_from_str(text, format) {
+            return Ok(Some(time.with_timezone(&Utc)));
+        }
+    }
+    for format in NAIVE_TIME_FORMATS {
+        if let Ok(time) = NaiveDateTime::parse_from_str(text, format) {
+            return Ok(Some(time.and_utc()));
+        }
+    }
+    Ok(None)
+}
+
+/// A row of `logs`.
+#[derive(Debug)]
+pub struct LogRow {
+    pub time: DateTime<Utc>,
+    pub ingest_time: DateTime<Utc>,
+    pub data: serde_json::Value,
+    pub level: String,
+    /// `source` field of the payload, kept in `data` as well
+    pub source: Option<String>,
+    pub words: Vec<String>,
+}
+
+/// A payload or row that cannot be stored in `logs`, kept in `rejected_logs`.
+#[derive(Debug, PartialEq)]
+pub struct Rejected {
+    pub reason: String,
+    pub payload: String,
+}
+
+impl Rejected {
+    pub fn new(reason: impl Into<String>, payload: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+            payload: payload.into(),
+        }
+    }
+}
+
+impl LogRow {
+    /// Build a row, taking its event time from the first of `time_fields`
+    /// that parses, or the ingest time otherwise.
+    /// Fails with the reason when the row cannot be stored, such as an epoch
+    /// time out of range.
+    pub fn new(
+        data: &serde_json::Map<String, serde_json::Value>,
+        time_fields: &[String],
+    ) -> Result<Self, String> {
+        static WORD: OnceLock<Regex> = OnceLock::new();
+        let word = WORD.get_or_init(|| Regex::new(r"[\w]+([-_][\w]+)*").unwrap());
+        let ingest_time = Utc::now();
+        let mut time = ingest_time;
+        for field in time_fields {
+            let Some(value) = data.get(field) else {
+                continue;
+            };
+            if let Some(parsed) =
+                parse_timestamp(value).map_err(|err| format!("{}: {}", field, err))?
+            {
+                time = parsed;
+                break;
+            }
+        }
+        let mut words = HashSet::new();
+        let mut try_words: Vec<serde_json::Value> = Vec::new();
+        let mut final_data: serde_json::Map<String, serde_json::Value> = data.clone();
+        let level = match data.get("level") {
+            None => "INFO".to_string(),
+            Some(serde_json::Value::String(level)) => level.to_string(),
+            Some(other) => return Err(format!("level is not a string: {}", other)),
+        };
+        if !level.is_empty() {
+            final_data.remove_entry("level");
+        }
+        let source = data
+            .get("source")
+            .and_then(serde_json::Value::as_str)
+            .map(str::to_owned);
+        try_words.push(final_data.to_owned().into());
+        while let Some(value) = try_words.pop() {
+            if let Some(try_str) = value.as_str() {
+                if try_str.contains('\0') {
+                    return Err("string contains a NUL character".to_string());
+                }
+                for cap in word.captures_iter(try_str) {
+                    words.insert(cap.get(0).unwrap().as_str().to_string());
+                }
+                continue;
+            }
+            if let Some(try_array) = value.as_array() {
+                for val in try_array {
+                    try_words.push(val.to_owned());
+                }
+                continue;
+            }
+            if let Some(try_nested) = value.as_object() {
+                for k in try_nested.keys() {
+                    if k.contains('\0') {
+                        return Err("key contains a NUL character".to_string());
+                    }
+                    words.insert(k.clone());
+                }
+                for val in try_nested.values() {
+                    try_words.push(val.to_owned());
+                }
+            }
+        }
+        Ok(Self {
+            time,
+            ingest_time,
+            data: final_data.into(),
+            level,
+            source,
+            words: words.into_iter().collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_timestamp() {
+        let expected = Utc.with_ymd_and_hms(2024, 3, 24, 17, 53, 44).unwrap();
+        for value in [
+            json!("2024-03-24T18:53:44+01:00"),
+            json!("Sun, 24 Mar 2024 17:53:44 +0000"),
+            json!("2024-03-24 17:53:44"),
+            json!("24/Mar/2024:17:53:44 +0000"),
+            json!(1711302824),
+            json!(1711302824000_i64),
+            json!(1711302824000000_i64),
+            json!(1711302824000000000_i64),
+            json!("1711302824"),
+            json!(1711302824.0),
+        ] {
+            assert_eq!(parse_timestamp(&value), Ok(Some(expected)), "{value}");
+        }
+        assert_eq!(parse_timestamp(&json!("yesterday")), Ok(None));
+        assert_eq!(parse_timestamp(&json!(true)), Ok(None));
+        // Too small or too large for any unit, or between two units
+        for value in [
+            json!(0),
+            json!(42),
+            json!(-1711302824),
+            json!(99999999999_i64),
+            json!(i64::MAX),
+            json!("1e300"),
+        ] {
+            assert!(parse_timestamp(&value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn test_log_row_time() {
+        let fields = vec!["ts".to_owned(), "time".to_owned()];
+        let data = json!({"time": "2024-03-24T17:53:44Z", "ts": "not a date"});
+        let row = LogRow::new(data.as_object().unwrap(), &fields).unwrap();
+        assert_eq!(
+            row.time,
+            Utc.with_ymd_and_hms(2024, 3, 24, 17, 53, 44).unwrap()
+        );
+        assert!(row.ingest_time > row.time);
+
+        let row = LogRow::new(json!({"msg": "hi"}).as_object().unwrap(), &fields).unwrap();
+        assert_eq!(row.time, row.ingest_time);
+
+        let data = json!({"ts": 42, "time": "2024-03-24T17:53:44Z"});
+        assert_eq!(
+            LogRow::new(data.as_object().unwrap(), &fields).unwrap_err(),
+            "ts: epoch 42 is not in seconds, millis, micros or nanos between 2000 and 2100"
+        );
+    }
+}
@@ -0,0 +1,3 @@
+ALTER TABLE logs ADD COLUMN id BIGSERIAL;
+
+CREATE INDEX idx_time_id ON logs (time, id);
@@ -0,0 +1 @@
+CREATE INDEX idx_id ON logs (id);
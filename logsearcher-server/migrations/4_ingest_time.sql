@@ -0,0 +1 @@
+ALTER TABLE logs ADD COLUMN ingest_time TIMESTAMP;
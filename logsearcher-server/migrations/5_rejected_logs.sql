@@ -0,0 +1,5 @@
+CREATE TABLE rejected_logs (
+    time TIMESTAMP,
+    reason TEXT,
+    payload TEXT
+);
@@ -0,0 +1,37 @@
+CREATE TABLE view_versions (
+    filter_name TEXT,
+    version INT,
+    time TIMESTAMP DEFAULT now(),
+    author TEXT,
+    query TEXT,
+    columns JSONB,
+    PRIMARY KEY (filter_name, version)
+);
+
+INSERT INTO
+    view_versions (filter_name, version, query, columns)
+SELECT
+    filters.name,
+    1,
+    filters.query,
+    COALESCE(
+        (
+            SELECT
+                jsonb_agg(
+                    jsonb_build_object(
+                        'name', cols.name,
+                        'query', COALESCE(cols.query, ''),
+                        'metric_agg', COALESCE(cols.metric_agg, '')
+                    )
+                    ORDER BY column_filter.idx
+                )
+            FROM
+                column_filter
+                JOIN cols ON cols.name = column_filter.column_name
+            WHERE
+                column_filter.filter_name = filters.name
+        ),
+        '[]'
+    )
+FROM
+    filters;
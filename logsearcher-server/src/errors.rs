@@ -1,21 +1,187 @@
-use axum::{http::StatusCode, response::IntoResponse};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{
+        header::{CONTENT_LENGTH, CONTENT_TYPE},
+        HeaderName, HeaderValue, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+
+use crate::filter::ParseError;
+
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
 
 #[derive(Debug)]
 pub enum AppError {
-    DBError(sqlx::error::Error),
+    NotFound(String),
+    BadRequest(String),
+    Conflict(String),
+    Timeout(String),
+    Unavailable(String),
+    TooLarge(String),
+    /// Logged with the request id, clients only get a generic message
+    Internal(String),
 }
 
 impl From<sqlx::error::Error> for AppError {
     fn from(error: sqlx::error::Error) -> Self {
-        Self::DBError(error)
+        match &error {
+            sqlx::Error::RowNotFound => Self::NotFound("not found".to_owned()),
+            sqlx::Error::PoolTimedOut => {
+                Self::Timeout("timed out waiting for a database connection".to_owned())
+            }
+            sqlx::Error::Database(db_error) => match db_error.code().as_deref() {
+                // unique_violation, duplicate_table
+                Some("23505" | "42P07") => Self::Conflict(db_error.message().to_owned()),
+                // query_canceled, raised by statement_timeout
+                Some("57014") => Self::Timeout("the query took too long".to_owned()),
+                _ => Self::Internal(error.to_string()),
+            },
+            _ => Self::Internal(error.to_string()),
+        }
+    }
+}
+
+impl From<ParseError> for AppError {
+    fn from(error: ParseError) -> Self {
+        Self::BadRequest(error.to_string())
     }
 }
 
+/// Code and message of an error response, rendered as JSON with the request
+/// id by [`request_id`].
+#[derive(Clone, Debug)]
+struct ErrorBody {
+    code: &'static str,
+    message: String,
+    /// Logged but not sent
+    detail: Option<String>,
+}
+
 impl IntoResponse for AppError {
-    fn into_response(self) -> axum::response::Response {
-        match self {
-            AppError::DBError(err) => tracing::error!(message = err.to_string()),
+    fn into_response(self) -> Response {
+        let (status, code, message) = match self {
+            AppError::NotFound(message) => (StatusCode::NOT_FOUND, "not_found", message),
+            AppError::BadRequest(message) => (StatusCode::BAD_REQUEST, "bad_request", message),
+            AppError::Conflict(message) => (StatusCode::CONFLICT, "conflict", message),
+            AppError::Timeout(message) => (StatusCode::GATEWAY_TIMEOUT, "timeout", message),
+            AppError::Unavailable(message) => {
+                (StatusCode::SERVICE_UNAVAILABLE, "unavailable", message)
+            }
+            AppError::TooLarge(message) => {
+                (StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", message)
+            }
+            AppError::Internal(detail) => (StatusCode::INTERNAL_SERVER_ERROR, "internal", detail),
+        };
+        let (message, detail) = match code {
+            "internal" => ("internal error".to_owned(), Some(message)),
+            _ => (message, None),
+        };
+        let mut response = status.into_response();
+        response.extensions_mut().insert(ErrorBody {
+            code,
+            message,
+            detail,
+        });
+        response
+    }
+}
+
+fn new_request_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    format!("{:016x}", hasher.finish())
+}
+
+/// Tag each request with an id, taken from its `x-request-id` header or
+/// generated, and sent back in the same header.
+///
+/// Error responses get a JSON body with their code, message and the request
+/// id, including the rejections of axum extractors (such as invalid JSON)
+/// and unknown routes, which come with a plain text body or none.
+pub async fn request_id(request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(REQUEST_ID)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= 64
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+        .map(str::to_owned)
+        .unwrap_or_else(new_request_id);
+    let response = next.run(request).await;
+    let status = response.status();
+    let mut response = if let Some(error) = response.extensions().get::<ErrorBody>().cloned() {
+        if let Some(detail) = &error.detail {
+            tracing::error!(request_id = id, message = detail);
         }
-        (StatusCode::INTERNAL_SERVER_ERROR, "Error".to_owned()).into_response()
+        render_error(response, error, &id)
+    } else if status.is_client_error() || status.is_server_error() {
+        let (parts, body) = response.into_parts();
+        let text = axum::body::to_bytes(body, 64 * 1024)
+            .await
+            .unwrap_or_default();
+        let text = String::from_utf8_lossy(&text);
+        let error = ErrorBody {
+            code: status_code_name(status),
+            message: match text.trim() {
+                "" => status.canonical_reason().unwrap_or("error").to_owned(),
+                text => text.to_owned(),
+            },
+            detail: None,
+        };
+        render_error(Response::from_parts(parts, Body::empty()), error, &id)
+    } else {
+        response
+    };
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID, value);
     }
+    response
+}
+
+/// `bad_request` for 400 Bad Request, and so on.
+fn status_code_name(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+        StatusCode::UNPROCESSABLE_ENTITY => "unprocessable_entity",
+        StatusCode::GATEWAY_TIMEOUT | StatusCode::REQUEST_TIMEOUT => "timeout",
+        StatusCode::SERVICE_UNAVAILABLE => "unavailable",
+        status if status.is_client_error() => "client_error",
+        _ => "internal",
+    }
+}
+
+/// Replace the body of an error response, keeping its status and headers.
+fn render_error(response: Response, error: ErrorBody, request_id: &str) -> Response {
+    let (mut parts, _) = response.into_parts();
+    let body = json!({
+        "code": error.code,
+        "message": error.message,
+        "request_id": request_id,
+    });
+    parts.headers.remove(CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    Response::from_parts(parts, Body::from(body.to_string()))
 }
@@ -0,0 +1,915 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgArguments, query::Query, Postgres};
+
+// Filter language used by views and columns.
+//
+//   expr    := or
+//   or      := and ("OR" and)*
+//   and     := not ("AND" not)*
+//   not     := "NOT" not | primary
+//   primary := "(" expr ")" | "TRUE" | "FALSE" | "EXISTS" field
+//            | field op value | field ["NOT"] "IN" "(" value ("," value)* ")"
+//   field   := "level" | "source" | "words" | "logdata" ("." segment)*
+//   op      := "=" | "==" | "!=" | "<>" | "<" | "<=" | ">" | ">="
+//   value   := string | number | "TRUE" | "FALSE" | "NULL"
+//
+// Examples: `level = "ERROR" AND logdata.host IN ("a", "b")`,
+// `NOT EXISTS logdata.user.id`, `words = "timeout" OR logdata.cpu > 0.9`.
+
+/// Deepest nesting of `NOT` and parentheses in a filter.
+const MAX_DEPTH: usize = 64;
+/// Most conditions joined by `AND` and `OR` in a filter, each one deepening
+/// the expression tree that `to_sql` walks.
+const MAX_TERMS: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "parse error at position {}: {}",
+            self.position, self.message
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    Level,
+    Source,
+    Words,
+    Data(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Str(String),
+    Num(serde_json::Number),
+    Bool(bool),
+    Null,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Const(bool),
+    Cmp(Field, CmpOp, Value),
+    In(Field, Vec<Value>),
+    Exists(Field),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+/// Parse a view filter. An empty filter matches every log.
+pub fn parse_filter(input: &str) -> Result<Expr, ParseError> {
+    let mut parser = Parser::new(input)?;
+    if parser.peek() == &Token::Eof {
+        return Ok(Expr::Const(true));
+    }
+    let expr = parser.parse_or()?;
+    parser.expect_eof()?;
+    Ok(expr)
+}
+
+/// Parse a column definition, which is a single field reference.
+pub fn parse_column(input: &str) -> Result<Field, ParseError> {
+    let mut parser = Parser::new(input)?;
+    let field = parser.parse_field()?;
+    parser.expect_eof()?;
+    Ok(field)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(String),
+    Dot,
+    Comma,
+    LParen,
+    RParen,
+    Op(CmpOp),
+    Eof,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(s) => write!(f, "`{s}`"),
+            Token::Str(s) => write!(f, "string {s:?}"),
+            Token::Num(s) => write!(f, "number {s}"),
+            Token::Dot => write!(f, "`.`"),
+            Token::Comma => write!(f, "`,`"),
+            Token::LParen => write!(f, "`(`"),
+            Token::RParen => write!(f, "`)`"),
+            Token::Op(_) => write!(f, "operator"),
+            Token::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let token = match c {
+            '.' => {
+                i += 1;
+                Token::Dot
+            }
+            ',' => {
+                i += 1;
+                Token::Comma
+            }
+            '(' => {
+                i += 1;
+                Token::LParen
+            }
+            ')' => {
+                i += 1;
+                Token::RParen
+            }
+            '=' => {
+                i += if chars.get(i + 1) == Some(&'=') { 2 } else { 1 };
+                Token::Op(CmpOp::Eq)
+            }
+            '!' if chars.get(i + 1) == Some(&'=') => {
+                i += 2;
+                Token::Op(CmpOp::Ne)
+            }
+            '<' => match chars.get(i + 1) {
+                Some('=') => {
+                    i += 2;
+                    Token::Op(CmpOp::Le)
+                }
+                Some('>') => {
+                    i += 2;
+                    Token::Op(CmpOp::Ne)
+                }
+                _ => {
+                    i += 1;
+                    Token::Op(CmpOp::Lt)
+                }
+            },
+            '>' => {
+                if chars.get(i + 1) == Some(&'=') {
+                    i += 2;
+                    Token::Op(CmpOp::Ge)
+                } else {
+                    i += 1;
+                    Token::Op(CmpOp::Gt)
+                }
+            }
+            '"' | '\'' => {
+                let quote = c;
+                let mut s = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => {
+                            return Err(ParseError {
+                                position: start,
+                                message: "unterminated string".to_owned(),
+                            })
+                        }
+                        Some(&ch) if ch == quote => {
+                            i += 1;
+                            break;
+                        }
+                        Some('\\') => {
+                            let escaped = match chars.get(i + 1) {
+                                Some('n') => '\n',
+                                Some('t') => '\t',
+                                Some(&ch @ ('\\' | '"' | '\'')) => ch,
+                                _ => {
+                                    return Err(ParseError {
+                                        position: i,
+                                        message: "invalid escape sequence".to_owned(),
+                                    })
+                                }
+                            };
+                            s.push(escaped);
+                            i += 2;
+                        }
+                        Some(&ch) => {
+                            s.push(ch);
+                            i += 1;
+                        }
+                    }
+                }
+                Token::Str(s)
+            }
+            c if c.is_ascii_digit()
+                || (c == '-' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit())) =>
+            {
+                // A number right after a dot is an array index in a path.
+                let path_index = matches!(tokens.last(), Some((_, Token::Dot)));
+                i += 1;
+                while i < chars.len()
+                    && (chars[i].is_ascii_digit()
+                        || (!path_index
+                            && (chars[i] == '.'
+                                || chars[i] == 'e'
+                                || chars[i] == 'E'
+                                || ((chars[i] == '-' || chars[i] == '+')
+                                    && (chars[i - 1] == 'e' || chars[i - 1] == 'E')))))
+                {
+                    i += 1;
+                }
+                Token::Num(chars[start..i].iter().collect())
+            }
+            c if c.is_alphabetic() || c == '_' || c == '@' => {
+                while i < chars.len()
+                    && (chars[i].is_alphanumeric()
+                        || chars[i] == '_'
+                        || chars[i] == '-'
+                        || chars[i] == '@')
+                {
+                    i += 1;
+                }
+                Token::Ident(chars[start..i].iter().collect())
+            }
+            _ => {
+                return Err(ParseError {
+                    position: start,
+                    message: format!("unexpected character {c:?}"),
+                })
+            }
+        };
+        tokens.push((start, token));
+    }
+    tokens.push((chars.len(), Token::Eof));
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    depth: usize,
+    terms: usize,
+}
+
+impl Parser {
+    fn new(input: &str) -> Result<Self, ParseError> {
+        Ok(Self {
+            tokens: tokenize(input)?,
+            pos: 0,
+            depth: 0,
+            terms: 0,
+        })
+    }
+
+    /// Enter a `NOT` or parentheses, failing past `MAX_DEPTH`.
+    fn nest(&mut self) -> Result<(), ParseError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return self.error(format!("filter nested deeper than {MAX_DEPTH} levels"));
+        }
+        Ok(())
+    }
+
+    /// Count a condition joined by `AND` or `OR`, failing past `MAX_TERMS`.
+    fn join(&mut self) -> Result<(), ParseError> {
+        self.terms += 1;
+        if self.terms > MAX_TERMS {
+            return self.error(format!("filter has more than {MAX_TERMS} conditions"));
+        }
+        Ok(())
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].1
+    }
+
+    fn position(&self) -> usize {
+        self.tokens[self.pos].0
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, ParseError> {
+        Err(ParseError {
+            position: self.position(),
+            message,
+        })
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Token::Ident(s) if s.eq_ignore_ascii_case(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek_keyword(keyword) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ParseError> {
+        if self.peek() == &expected {
+            self.pos += 1;
+            return Ok(());
+        }
+        self.error(format!("expected {}, found {}", expected, self.peek()))
+    }
+
+    fn expect_eof(&self) -> Result<(), ParseError> {
+        if self.peek() != &Token::Eof {
+            return self.error(format!("unexpected {}", self.peek()));
+        }
+        Ok(())
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_and()?;
+        while self.eat_keyword("or") {
+            self.join()?;
+            let right = self.parse_and()?;
+            left = Expr::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_not()?;
+        while self.eat_keyword("and") {
+            self.join()?;
+            let right = self.parse_not()?;
+            left = Expr::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, ParseError> {
+        if self.eat_keyword("not") {
+            self.nest()?;
+            let expr = Expr::Not(Box::new(self.parse_not()?));
+            self.depth -= 1;
+            return Ok(expr);
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        if self.peek() == &Token::LParen {
+            self.pos += 1;
+            self.nest()?;
+            let expr = self.parse_or()?;
+            self.expect(Token::RParen)?;
+            self.depth -= 1;
+            return Ok(expr);
+        }
+        if self.eat_keyword("true") {
+            return Ok(Expr::Const(true));
+        }
+        if self.eat_keyword("false") {
+            return Ok(Expr::Const(false));
+        }
+        if self.eat_keyword("exists") {
+            return Ok(Expr::Exists(self.parse_field()?));
+        }
+        let field_position = self.position();
+        let field = self.parse_field()?;
+        let negated = self.eat_keyword("not");
+        if self.eat_keyword("in") {
+            self.expect(Token::LParen)?;
+            let mut values = vec![self.parse_value()?];
+            while self.peek() == &Token::Comma {
+                self.pos += 1;
+                values.push(self.parse_value()?);
+            }
+            self.expect(Token::RParen)?;
+            check_in(&field, &values, field_position)?;
+            let expr = Expr::In(field, values);
+            return Ok(if negated {
+                Expr::Not(Box::new(expr))
+            } else {
+                expr
+            });
+        }
+        if negated {
+            return self.error(format!("expected `IN`, found {}", self.peek()));
+        }
+        let op = match self.peek() {
+            Token::Op(op) => *op,
+            other => return self.error(format!("expected comparison operator, found {other}")),
+        };
+        self.pos += 1;
+        let value = self.parse_value()?;
+        check_cmp(&field, op, &value, field_position)?;
+        Ok(Expr::Cmp(field, op, value))
+    }
+
+    fn parse_field(&mut self) -> Result<Field, ParseError> {
+        let name = match self.peek() {
+            Token::Ident(name) => name.to_ascii_lowercase(),
+            other => return self.error(format!("expected field, found {other}")),
+        };
+        let field = match name.as_str() {
+            "level" => Field::Level,
+            "source" => Field::Source,
+            "words" => Field::Words,
+            "logdata" => Field::Data(Vec::new()),
+            _ => {
+                return self.error(format!(
+                    "unknown field `{name}`, expected level, source, words or logdata.<path>"
+                ))
+            }
+        };
+        self.pos += 1;
+        let Field::Data(mut path) = field else {
+            return Ok(field);
+        };
+        while self.peek() == &Token::Dot {
+            self.pos += 1;
+            match self.peek() {
+                Token::Ident(s) | Token::Str(s) => path.push(s.clone()),
+                Token::Num(s) if s.chars().all(|c| c.is_ascii_digit()) => path.push(s.clone()),
+                other => return self.error(format!("expected path segment, found {other}")),
+            }
+            self.pos += 1;
+        }
+        Ok(Field::Data(path))
+    }
+
+    fn parse_value(&mut self) -> Result<Value, ParseError> {
+        let value = match self.peek() {
+            Token::Str(s) => Value::Str(s.clone()),
+            Token::Num(s) => match serde_json::from_str::<serde_json::Number>(s) {
+                Ok(num) => Value::Num(num),
+                Err(_) => return self.error(format!("invalid number {s}")),
+            },
+            Token::Ident(s) if s.eq_ignore_ascii_case("true") => Value::Bool(true),
+            Token::Ident(s) if s.eq_ignore_ascii_case("false") => Value::Bool(false),
+            Token::Ident(s) if s.eq_ignore_ascii_case("null") => Value::Null,
+            other => return self.error(format!("expected value, found {other}")),
+        };
+        self.pos += 1;
+        Ok(value)
+    }
+}
+
+fn check_cmp(field: &Field, op: CmpOp, value: &Value, position: usize) -> Result<(), ParseError> {
+    let message = match (field, op, value) {
+        (Field::Data(_), CmpOp::Eq | CmpOp::Ne, _) => return Ok(()),
+        (Field::Data(_), _, Value::Null | Value::Bool(_)) => {
+            "ordering comparisons need a string or number value"
+        }
+        (Field::Data(_), _, _) => return Ok(()),
+        (Field::Words, CmpOp::Eq | CmpOp::Ne, Value::Str(_)) => return Ok(()),
+        (Field::Words, _, _) => "words only supports `=`, `!=` and `IN` with string values",
+        (Field::Level | Field::Source, CmpOp::Eq | CmpOp::Ne, Value::Null | Value::Str(_)) => {
+            return Ok(())
+        }
+        (Field::Level | Field::Source, _, Value::Str(_)) => return Ok(()),
+        (Field::Level | Field::Source, _, _) => "level and source only compare to strings",
+    };
+    Err(ParseError {
+        position,
+        message: message.to_owned(),
+    })
+}
+
+fn check_in(field: &Field, values: &[Value], position: usize) -> Result<(), ParseError> {
+    match field {
+        Field::Data(_) => Ok(()),
+        _ if values.iter().all(|v| matches!(v, Value::Str(_))) => Ok(()),
+        _ => Err(ParseError {
+            position,
+            message: "level, source and words only match string values".to_owned(),
+        }),
+    }
+}
+
+/// A value bound to a rendered SQL statement.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlParam {
+    Text(String),
+    TextArray(Vec<String>),
+    Json(serde_json::Value),
+}
+
+impl SqlParam {
+    fn sql_type(&self) -> &'static str {
+        match self {
+            SqlParam::Text(_) => "text",
+            SqlParam::TextArray(_) => "text[]",
+            SqlParam::Json(_) => "jsonb",
+        }
+    }
+
+    fn to_literal(&self) -> String {
+        match self {
+            SqlParam::Text(s) => quote_literal(s),
+            SqlParam::TextArray(v) => format!(
+                "ARRAY[{}]",
+                v.iter()
+                    .map(|s| quote_literal(s))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            SqlParam::Json(v) => quote_literal(&v.to_string()),
+        }
+    }
+}
+
+fn quote_literal(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// Where rendered filters put their values: either bind parameters or,
+/// for statements that cannot take parameters (DDL), escaped literals.
+pub trait SqlParams {
+    fn push(&mut self, param: SqlParam) -> String;
+}
+
+/// Collects bind parameters, numbered after `offset` already bound ones.
+#[derive(Debug, Default)]
+pub struct BoundParams {
+    offset: usize,
+    values: Vec<SqlParam>,
+}
+
+impl BoundParams {
+    pub fn new(offset: usize) -> Self {
+        Self {
+            offset,
+            values: Vec::new(),
+        }
+    }
+
+    pub fn bind<'q>(
+        self,
+        mut query: Query<'q, Postgres, PgArguments>,
+    ) -> Query<'q, Postgres, PgArguments> {
+        for value in self.values {
+            query = match value {
+                SqlParam::Text(s) => query.bind(s),
+                SqlParam::TextArray(v) => query.bind(v),
+                SqlParam::Json(v) => query.bind(v),
+            };
+        }
+        query
+    }
+}
+
+impl SqlParams for BoundParams {
+    fn push(&mut self, param: SqlParam) -> String {
+        let ty = param.sql_type();
+        self.values.push(param);
+        format!("${}::{}", self.offset + self.values.len(), ty)
+    }
+}
+
+/// Renders values as quoted literals.
+pub struct InlineParams;
+
+impl SqlParams for InlineParams {
+    fn push(&mut self, param: SqlParam) -> String {
+        format!("{}::{}", param.to_literal(), param.sql_type())
+    }
+}
+
+fn value_to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Str(s) => s.clone().into(),
+        Value::Num(n) => n.clone().into(),
+        Value::Bool(b) => (*b).into(),
+        Value::Null => serde_json::Value::Null,
+    }
+}
+
+fn value_to_text(value: &Value) -> String {
+    match value {
+        Value::Str(s) => s.clone(),
+        other => value_to_json(other).to_string(),
+    }
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Str(_) => "string",
+        Value::Num(_) => "number",
+        Value::Bool(_) => "boolean",
+        Value::Null => "null",
+    }
+}
+
+fn op_sql(op: CmpOp) -> &'static str {
+    match op {
+        CmpOp::Eq => "=",
+        CmpOp::Ne => "<>",
+        CmpOp::Lt => "<",
+        CmpOp::Le => "<=",
+        CmpOp::Gt => ">",
+        CmpOp::Ge => ">=",
+    }
+}
+
+impl Field {
+    /// SQL expression for the field, always typed as jsonb.
+    pub fn to_sql(&self, params: &mut impl SqlParams) -> String {
+        match self {
+            Field::Level => "to_jsonb(level)".to_owned(),
+            Field::Source => "to_jsonb(source)".to_owned(),
+            Field::Words => "to_jsonb(words)".to_owned(),
+            Field::Data(path) if path.is_empty() => "logdata".to_owned(),
+            Field::Data(path) => format!(
+                "(logdata #> {})",
+                params.push(SqlParam::TextArray(path.clone()))
+            ),
+        }
+    }
+
+    fn column(&self) -> &'static str {
+        match self {
+            Field::Level => "level",
+            Field::Source => "source",
+            Field::Words => "words",
+            Field::Data(_) => "logdata",
+        }
+    }
+}
+
+/// Build `{"a": {"b": value}}` for a path, used with `@>` so the GIN index applies.
+fn containment(path: &[String], value: &Value) -> serde_json::Value {
+    path.iter().rev().fold(value_to_json(value), |acc, key| {
+        let mut map = serde_json::Map::new();
+        map.insert(key.clone(), acc);
+        map.into()
+    })
+}
+
+fn data_eq(path: &[String], value: &Value, params: &mut impl SqlParams) -> String {
+    if !path.is_empty() && path.iter().all(|s| !s.chars().all(|c| c.is_ascii_digit())) {
+        return format!(
+            "logdata @> {}",
+            params.push(SqlParam::Json(containment(path, value)))
+        );
+    }
+    let field = Field::Data(path.to_vec()).to_sql(params);
+    format!(
+        "{} IS NOT DISTINCT FROM {}",
+        field,
+        params.push(SqlParam::Json(value_to_json(value)))
+    )
+}
+
+impl Expr {
+    /// Render the filter as a SQL boolean expression over the `logs` table.
+    pub fn to_sql(&self, params: &mut impl SqlParams) -> String {
+        match self {
+            Expr::Const(true) => "true".to_owned(),
+            Expr::Const(false) => "false".to_owned(),
+            Expr::Not(inner) => format!("NOT ({})", inner.to_sql(params)),
+            Expr::And(l, r) => format!("({} AND {})", l.to_sql(params), r.to_sql(params)),
+            Expr::Or(l, r) => format!("({} OR {})", l.to_sql(params), r.to_sql(params)),
+            Expr::Exists(Field::Words) => "cardinality(words) > 0".to_owned(),
+            Expr::Exists(Field::Data(path)) if path.len() == 1 => {
+                format!("logdata ? {}", params.push(SqlParam::Text(path[0].clone())))
+            }
+            Expr::Exists(field @ Field::Data(_)) => format!("{} IS NOT NULL", field.to_sql(params)),
+            Expr::Exists(field) => format!("{} IS NOT NULL", field.column()),
+            Expr::In(Field::Words, values) => format!(
+                "words && {}",
+                params.push(SqlParam::TextArray(
+                    values.iter().map(value_to_text).collect()
+                ))
+            ),
+            Expr::In(Field::Data(path), values) => format!(
+                "({})",
+                values
+                    .iter()
+                    .map(|v| data_eq(path, v, params))
+                    .collect::<Vec<_>>()
+                    .join(" OR ")
+            ),
+            Expr::In(field, values) => format!(
+                "{} = ANY({})",
+                field.column(),
+                params.push(SqlParam::TextArray(
+                    values.iter().map(value_to_text).collect()
+                ))
+            ),
+            Expr::Cmp(Field::Words, op, value) => {
+                let contains = format!(
+                    "words @> {}",
+                    params.push(SqlParam::TextArray(vec![value_to_text(value)]))
+                );
+                match op {
+                    CmpOp::Ne => format!("NOT ({contains})"),
+                    _ => contains,
+                }
+            }
+            Expr::Cmp(Field::Data(path), CmpOp::Eq, value) => data_eq(path, value, params),
+            Expr::Cmp(Field::Data(path), CmpOp::Ne, value) => {
+                format!("NOT ({})", data_eq(path, value, params))
+            }
+            Expr::Cmp(field @ Field::Data(_), op, value) => {
+                let field_sql = field.to_sql(params);
+                let type_param = params.push(SqlParam::Text(json_type(value).to_owned()));
+                let value_param = params.push(SqlParam::Json(value_to_json(value)));
+                format!(
+                    "(CASE WHEN jsonb_typeof({field_sql}) = {type_param} THEN {field_sql} {} {value_param} ELSE false END)",
+                    op_sql(*op)
+                )
+            }
+            Expr::Cmp(field, CmpOp::Eq, Value::Null) => format!("{} IS NULL", field.column()),
+            Expr::Cmp(field, CmpOp::Ne, Value::Null) => format!("{} IS NOT NULL", field.column()),
+            Expr::Cmp(field, op, value) => format!(
+                "{} {} {}",
+                field.column(),
+                op_sql(*op),
+                params.push(SqlParam::Text(value_to_text(value)))
+            ),
+        }
+    }
+}
+
+/// How a full-text search combines its terms.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WordMatch {
+    #[default]
+    All,
+    Any,
+    None,
+}
+
+impl WordMatch {
+    /// Condition on the `words` column, written with array operators so the GIN index applies.
+    pub fn to_sql(self, terms: &[String], params: &mut impl SqlParams) -> String {
+        let terms = params.push(SqlParam::TextArray(terms.to_vec()));
+        match self {
+            WordMatch::All => format!("words @> {terms}"),
+            WordMatch::Any => format!("words && {terms}"),
+            WordMatch::None => format!("NOT (words && {terms})"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(input: &str) -> (String, Vec<SqlParam>) {
+        let mut params = BoundParams::new(0);
+        let sql = parse_filter(input).unwrap().to_sql(&mut params);
+        (sql, params.values)
+    }
+
+    #[test]
+    fn test_empty_and_const() {
+        assert_eq!(parse_filter("").unwrap(), Expr::Const(true));
+        assert_eq!(parse_filter("  true ").unwrap(), Expr::Const(true));
+        assert_eq!(render("FALSE").0, "false");
+    }
+
+    #[test]
+    fn test_precedence() {
+        let expr = parse_filter("level = 'ERROR' OR NOT source = 'a' AND words = 'x'").unwrap();
+        assert_eq!(
+            expr,
+            Expr::Or(
+                Box::new(Expr::Cmp(
+                    Field::Level,
+                    CmpOp::Eq,
+                    Value::Str("ERROR".to_owned())
+                )),
+                Box::new(Expr::And(
+                    Box::new(Expr::Not(Box::new(Expr::Cmp(
+                        Field::Source,
+                        CmpOp::Eq,
+                        Value::Str("a".to_owned())
+                    )))),
+                    Box::new(Expr::Cmp(
+                        Field::Words,
+                        CmpOp::Eq,
+                        Value::Str("x".to_owned())
+                    ))
+                ))
+            )
+        );
+    }
+
+    #[test]
+    fn test_parameterized_sql() {
+        let (sql, params) = render("logdata.host = \"web'1\" AND level IN ('WARNING', 'ERROR')");
+        assert_eq!(sql, "(logdata @> $1::jsonb AND level = ANY($2::text[]))");
+        assert_eq!(
+            params,
+            vec![
+                SqlParam::Json(serde_json::json!({"host": "web'1"})),
+                SqlParam::TextArray(vec!["WARNING".to_owned(), "ERROR".to_owned()])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_data_ordering() {
+        let (sql, params) = render("logdata.cpu.load >= 0.5");
+        assert_eq!(
+            sql,
+            "(CASE WHEN jsonb_typeof((logdata #> $1::text[])) = $2::text THEN (logdata #> $1::text[]) >= $3::jsonb ELSE false END)"
+        );
+        assert_eq!(params[1], SqlParam::Text("number".to_owned()));
+    }
+
+    #[test]
+    fn test_words_and_exists() {
+        assert_eq!(render("words IN ('a', 'b')").0, "words && $1::text[]");
+        assert_eq!(
+            render("NOT EXISTS logdata.user").0,
+            "NOT (logdata ? $1::text)"
+        );
+        assert_eq!(render("words NOT IN ('a')").0, "NOT (words && $1::text[])");
+    }
+
+    #[test]
+    fn test_word_match() {
+        let terms = vec!["a".to_owned(), "b".to_owned()];
+        let mut params = BoundParams::new(2);
+        assert_eq!(
+            WordMatch::All.to_sql(&terms, &mut params),
+            "words @> $3::text[]"
+        );
+        assert_eq!(
+            WordMatch::None.to_sql(&terms, &mut params),
+            "NOT (words && $4::text[])"
+        );
+    }
+
+    #[test]
+    fn test_inline_literals_are_escaped() {
+        let sql = parse_filter("source = \"it's\"")
+            .unwrap()
+            .to_sql(&mut InlineParams);
+        assert_eq!(sql, "source = 'it''s'::text");
+    }
+
+    #[test]
+    fn test_errors_report_position() {
+        let err = parse_filter("level = 'INFO' AND").unwrap_err();
+        assert_eq!(err.position, 18);
+        let err = parse_filter("level = 1").unwrap_err();
+        assert_eq!(err.position, 0);
+        let err = parse_filter("1 = 1").unwrap_err();
+        assert_eq!(err.position, 0);
+        let err = parse_filter("logdata.a = 'x' ;").unwrap_err();
+        assert_eq!(err.message, "unexpected character ';'");
+        assert_eq!(err.position, 16);
+    }
+
+    #[test]
+    fn test_nesting_limits() {
+        let nested = format!("{}level = 'INFO'{}", "(NOT ".repeat(32), ")".repeat(32));
+        assert!(parse_filter(&nested).is_ok());
+        for input in [
+            format!("{}level = 'INFO'", "NOT ".repeat(300_000)),
+            format!("{}level = 'INFO'", "(".repeat(300_000)),
+        ] {
+            let err = parse_filter(&input).unwrap_err();
+            assert_eq!(err.message, "filter nested deeper than 64 levels");
+        }
+        let chain = vec!["level = 'INFO'"; 257].join(" AND ");
+        assert!(parse_filter(&chain).is_ok());
+        let chain = vec!["level = 'INFO'"; 300_000].join(" OR ");
+        let err = parse_filter(&chain).unwrap_err();
+        assert_eq!(err.message, "filter has more than 256 conditions");
+    }
+
+    #[test]
+    fn test_parse_column() {
+        assert_eq!(parse_column("logdata").unwrap(), Field::Data(vec![]));
+        assert_eq!(
+            parse_column("logdata.items.0.\"a b\"").unwrap(),
+            Field::Data(vec!["items".to_owned(), "0".to_owned(), "a b".to_owned()])
+        );
+        assert!(parse_column("logdata->'a'").is_err());
+    }
+}
@@ -1,29 +1,76 @@
 use axum::{
-    extract::{Json, Path, State},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{delete, get, post},
+    body::{Body, Bytes},
+    extract::{Json, Path, Query, State},
+    http::{
+        header::{CONTENT_ENCODING, CONTENT_TYPE},
+        HeaderMap, StatusCode,
+    },
+    response::{
+        sse::{KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
     Router,
 };
 use serde_json::json;
 use std::iter::zip;
 
-use crate::errors::AppError;
+use crate::errors::{self, AppError};
+use crate::filter;
+use crate::ingest;
+use crate::otlp;
+use crate::repository::bucket_starts;
+use crate::tail::log_stream;
 use crate::{
-    model::{LogQuery, MetricQuery, ViewQuery},
+    model::{
+        ColumnDef, ColumnPatch, DiffQuery, GrafanaAnnotationQuery, GrafanaQuery, GrafanaSearch,
+        LogQuery, MetricQuery, RollbackQuery, SearchQuery, TailQuery, ViewDiff, ViewPatch,
+        ViewQuery,
+    },
     AppState,
 };
+use prost::Message;
 
 pub fn app() -> Router<AppState> {
     Router::new()
         .route("/api/health", get(health_checker_handler))
         .route("/api/density", post(density_handler))
         .route("/api/logs", post(logs_handler))
+        .route("/api/search", post(search_handler))
+        .route("/api/ingest", post(ingest_handler))
+        .route("/v1/logs", post(otlp_logs_handler))
+        .route("/api/tail/:view_name", get(tail_handler))
         .route("/api/listviews", get(list_views))
         .route("/api/view", post(create_view_handler))
-        .route("/api/view/:view_name", delete(delete_view_handler))
+        .route(
+            "/api/view/:view_name",
+            get(get_view_handler)
+                .patch(patch_view_handler)
+                .delete(delete_view_handler),
+        )
+        .route("/api/view/:view_name/versions", get(list_versions_handler))
+        .route("/api/view/:view_name/diff", get(diff_versions_handler))
+        .route("/api/view/:view_name/rollback", post(rollback_view_handler))
+        .route(
+            "/api/column",
+            get(list_columns_handler).post(create_column_handler),
+        )
+        .route(
+            "/api/column/:column_name",
+            get(get_column_handler)
+                .patch(patch_column_handler)
+                .delete(delete_column_handler),
+        )
         .route("/api/metric", get(list_metrics))
         .route("/api/get/metric", post(post_get_metric))
+        .route("/api/grafana", get(health_checker_handler))
+        .route("/api/grafana/search", post(grafana_search_handler))
+        .route("/api/grafana/query", post(grafana_query_handler))
+        .route(
+            "/api/grafana/annotations",
+            post(grafana_annotations_handler),
+        )
+        .layer(axum::middleware::from_fn(errors::request_id))
 }
 
 pub async fn health_checker_handler() -> impl IntoResponse {
@@ -61,46 +108,330 @@ pub async fn logs_handler(
             .get_logs(
                 log_query.start.naive_utc(),
                 log_query.end.naive_utc(),
-                log_query.offset,
+                &log_query.page,
                 log_query.table.to_owned(),
+                None,
             )
             .await?,
     ))
 }
 
+pub async fn search_handler(
+    State(data): State<AppState>,
+    Json(search_query): Json<SearchQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let terms: Vec<String> = search_query
+        .terms
+        .iter()
+        .flat_map(|term| term.split_whitespace())
+        .map(|term| term.to_owned())
+        .collect();
+    if terms.is_empty() {
+        return Err(AppError::BadRequest(
+            "search needs at least one term".to_owned(),
+        ));
+    }
+    let start = search_query.start.naive_utc();
+    let end = search_query.end.naive_utc();
+    let logs = data
+        .db
+        .get_logs(
+            start,
+            end,
+            &search_query.page,
+            search_query.table.to_owned(),
+            Some((search_query.mode, &terms)),
+        )
+        .await?;
+    let density = data
+        .db
+        .get_search_density(start, end, &search_query.table, search_query.mode, &terms)
+        .await?;
+    Ok(axum::Json(
+        json!({"logs": logs.logs, "next_cursor": logs.next_cursor, "density": density}),
+    ))
+}
+
+async fn read_body(body: Body, max_bytes: usize) -> Result<Bytes, AppError> {
+    axum::body::to_bytes(body, max_bytes)
+        .await
+        .map_err(|_| AppError::TooLarge(format!("body is over {} bytes", max_bytes)))
+}
+
+/// Store logs posted as a JSON array or NDJSON, without going through
+/// RabbitMQ. Records that cannot be stored go to `rejected_logs`.
+pub async fn ingest_handler(
+    State(data): State<AppState>,
+    body: Body,
+) -> Result<impl IntoResponse, AppError> {
+    let body = read_body(body, data.ingest.max_bytes).await?;
+    let body = std::str::from_utf8(&body)
+        .map_err(|err| AppError::BadRequest(format!("body is not UTF-8: {}", err)))?;
+    let (rows, rejected) =
+        ingest::parse_body(body, &data.ingest.time_fields).map_err(AppError::BadRequest)?;
+    let accepted = data.db.insert_logs(&rows, &rejected).await?;
+    Ok(axum::Json(
+        json!({"accepted": accepted, "rejected": rejected.len()}),
+    ))
+}
+
+/// OTLP/HTTP logs receiver, answering in the encoding of the request.
+pub async fn otlp_logs_handler(
+    State(data): State<AppState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, AppError> {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+    };
+    if !matches!(header(CONTENT_ENCODING), "" | "identity") {
+        return Err(AppError::BadRequest(format!(
+            "unsupported Content-Encoding {}",
+            header(CONTENT_ENCODING)
+        )));
+    }
+    let json = match header(CONTENT_TYPE).split(';').next().unwrap_or_default() {
+        "application/json" => true,
+        "application/x-protobuf" | "application/protobuf" => false,
+        other => {
+            return Err(AppError::BadRequest(format!(
+                "unsupported Content-Type {}, expected application/x-protobuf or application/json",
+                other
+            )))
+        }
+    };
+    let body = read_body(body, data.ingest.max_bytes).await?;
+    let request = if json {
+        otlp::parse_json(&body).map_err(AppError::BadRequest)?
+    } else {
+        otlp::ExportLogsServiceRequest::decode(body)
+            .map_err(|err| AppError::BadRequest(format!("invalid protobuf: {}", err)))?
+    };
+    let (rows, rejected) = otlp::parse_request(&request);
+    data.db.insert_logs(&rows, &rejected).await?;
+    let partial_success = rejected
+        .first()
+        .map(|rejection| otlp::ExportLogsPartialSuccess {
+            rejected_log_records: rejected.len() as i64,
+            error_message: rejection.reason.clone(),
+        });
+    if json {
+        let response = match partial_success {
+            Some(partial) => json!({"partialSuccess": {
+                "rejectedLogRecords": partial.rejected_log_records.to_string(),
+                "errorMessage": partial.error_message,
+            }}),
+            None => json!({}),
+        };
+        return Ok(axum::Json(response).into_response());
+    }
+    let response = otlp::ExportLogsServiceResponse { partial_success };
+    Ok((
+        [(CONTENT_TYPE, "application/x-protobuf")],
+        response.encode_to_vec(),
+    )
+        .into_response())
+}
+
+pub async fn tail_handler(
+    State(data): State<AppState>,
+    Path(view_name): Path<String>,
+    Query(tail_query): Query<TailQuery>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let permit = data
+        .tail
+        .try_acquire()
+        .ok_or_else(|| AppError::Unavailable("too many live tail clients".to_owned()))?;
+    let search = tail_query
+        .terms
+        .map(|terms| {
+            terms
+                .split_whitespace()
+                .map(|term| term.to_owned())
+                .collect::<Vec<String>>()
+        })
+        .filter(|terms| !terms.is_empty())
+        .map(|terms| (tail_query.mode, terms));
+    // Check the view before streaming, so errors get a proper status.
+    data.db
+        .tail_logs(
+            &view_name,
+            search
+                .as_ref()
+                .map(|(mode, terms)| (*mode, terms.as_slice())),
+            i64::MAX,
+            &[],
+            1,
+        )
+        .await?;
+    // A reconnecting EventSource resumes after the last log it received.
+    let last_id = match headers
+        .get("Last-Event-ID")
+        .and_then(|id| id.to_str().ok()?.parse::<i64>().ok())
+    {
+        Some(id) => id,
+        None => data.db.max_log_id().await?,
+    };
+    let stream = log_stream(data.db, &data.tail, permit, view_name, search, last_id);
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+pub async fn get_view_handler(
+    State(data): State<AppState>,
+    Path(view_name): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(axum::Json(data.db.get_view(&view_name).await?))
+}
+
+/// Rename a view or change its filter or columns, answering with the new definition.
+pub async fn patch_view_handler(
+    State(data): State<AppState>,
+    Path(view_name): Path<String>,
+    headers: HeaderMap,
+    Json(patch): Json<ViewPatch>,
+) -> Result<impl IntoResponse, AppError> {
+    if let Some(query) = &patch.query {
+        filter::parse_filter(query)?;
+    }
+    let view_name = data
+        .db
+        .update_view(&view_name, &patch, author(&headers))
+        .await?;
+    Ok(axum::Json(data.db.get_view(&view_name).await?))
+}
+
+/// Author of a change to a view or column, as set by an authenticating proxy.
+fn author(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("x-forwarded-user")
+        .and_then(|user| user.to_str().ok())
+        .filter(|user| !user.is_empty())
+}
+
+pub async fn list_versions_handler(
+    State(data): State<AppState>,
+    Path(view_name): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(axum::Json(data.db.list_versions(&view_name).await?))
+}
+
+pub async fn diff_versions_handler(
+    State(data): State<AppState>,
+    Path(view_name): Path<String>,
+    Query(query): Query<DiffQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let from = data.db.get_version(&view_name, query.from).await?;
+    let to = data.db.get_version(&view_name, query.to).await?;
+    Ok(axum::Json(ViewDiff::new(&from, &to)))
+}
+
+/// Restore a version of a view, answering with the restored definition.
+pub async fn rollback_view_handler(
+    State(data): State<AppState>,
+    Path(view_name): Path<String>,
+    headers: HeaderMap,
+    Json(rollback): Json<RollbackQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    data.db
+        .rollback_view(&view_name, rollback.version, author(&headers))
+        .await?;
+    Ok(axum::Json(data.db.get_view(&view_name).await?))
+}
+
 pub async fn delete_view_handler(
     State(data): State<AppState>,
     Path(view_name): Path<String>,
 ) -> Result<impl IntoResponse, AppError> {
-    data.db.delete_view(view_name).await?;
+    data.db.delete_view(&view_name).await?;
     Ok(StatusCode::OK)
 }
 
 pub async fn create_view_handler(
     State(data): State<AppState>,
+    headers: HeaderMap,
     log_query: Json<ViewQuery>,
 ) -> Result<impl IntoResponse, AppError> {
     let filter_name = log_query.filter.name.to_owned();
     let filter_query = log_query.filter.query.to_owned();
-    let (names, queries) = log_query
+    let filter = filter::parse_filter(&filter_query)?;
+    for column in log_query.columns.iter() {
+        filter::parse_column(&column.query)?;
+    }
+    let (names, queries): (Vec<String>, Vec<(String, String)>) = log_query
         .0
         .columns
         .into_iter()
         .map(|c| (c.name, (c.query, c.metric_agg)))
         .unzip();
-    let filter_name = if filter_name.len() == 0 {
+    let filter_name = if filter_name.is_empty() {
         "logs".to_owned()
     } else {
         filter_name
     };
     data.db
-        .upsert_columns_and_filters(&names, &queries, &filter_name, &filter_query)
+        .upsert_columns_and_filters(
+            &names,
+            &queries,
+            &filter_name,
+            &filter_query,
+            &filter,
+            author(&headers),
+        )
         .await?;
-
-    data.db.create_mat_views(filter_name, filter_query).await?;
     Ok((StatusCode::CREATED, "{}".to_string()))
 }
 
+pub async fn list_columns_handler(
+    State(data): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(axum::Json(data.db.list_columns().await?))
+}
+
+pub async fn get_column_handler(
+    State(data): State<AppState>,
+    Path(column_name): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(axum::Json(data.db.get_column(&column_name).await?))
+}
+
+pub async fn create_column_handler(
+    State(data): State<AppState>,
+    Json(column): Json<ColumnDef>,
+) -> Result<impl IntoResponse, AppError> {
+    filter::parse_column(&column.query)?;
+    data.db.create_column(&column).await?;
+    Ok((StatusCode::CREATED, axum::Json(column)))
+}
+
+/// Change a column for every view using it, answering with the new definition.
+pub async fn patch_column_handler(
+    State(data): State<AppState>,
+    Path(column_name): Path<String>,
+    headers: HeaderMap,
+    Json(patch): Json<ColumnPatch>,
+) -> Result<impl IntoResponse, AppError> {
+    if let Some(query) = &patch.query {
+        filter::parse_column(query)?;
+    }
+    let column_name = data
+        .db
+        .update_column(&column_name, &patch, author(&headers))
+        .await?;
+    Ok(axum::Json(data.db.get_column(&column_name).await?))
+}
+
+pub async fn delete_column_handler(
+    State(data): State<AppState>,
+    Path(column_name): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    data.db.delete_column(&column_name).await?;
+    Ok(StatusCode::OK)
+}
+
 pub async fn list_metrics(State(data): State<AppState>) -> Result<impl IntoResponse, AppError> {
     Ok(axum::Json(data.db.get_col_names().await?))
 }
@@ -127,6 +458,101 @@ pub async fn post_get_metric(
     ))
 }
 
+/// Grafana JSON data source targets: `view` for the log count of a view,
+/// and `view/column` for each of its columns.
+pub async fn grafana_search_handler(
+    State(data): State<AppState>,
+    Json(search): Json<GrafanaSearch>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut targets = Vec::new();
+    for (name, _, metrics) in data.db.list_filters().await? {
+        targets.extend(metrics.iter().map(|metric| format!("{}/{}", name, metric)));
+        targets.push(name);
+    }
+    targets.retain(|target| target.contains(search.target.as_str()));
+    targets.sort();
+    Ok(axum::Json(targets))
+}
+
+/// Time series of the Grafana targets, as `[value, epoch millis]` points.
+pub async fn grafana_query_handler(
+    State(data): State<AppState>,
+    Json(query): Json<GrafanaQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let (from, to) = (query.range.from, query.range.to);
+    let mut series = Vec::new();
+    for target in query.targets.iter().filter(|target| !target.hide) {
+        let (view, metric) = match target.target.split_once('/') {
+            Some((view, metric)) => (view, Some(metric)),
+            None => (target.target.as_str(), None),
+        };
+        let values: Vec<serde_json::Value> = match metric {
+            None => data
+                .db
+                .get_density(from.naive_utc(), to.naive_utc(), view)
+                .await?
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            Some(metric) => {
+                let (filter_query, col_query, metric_agg) =
+                    data.db.get_view_column(view, metric).await?;
+                data.db
+                    .get_filters(from, to, metric_agg, col_query, filter_query)
+                    .await?
+                    .into_iter()
+                    .map(Into::into)
+                    .collect()
+            }
+        };
+        let times = bucket_starts(from.naive_utc(), to.naive_utc(), values.len());
+        let datapoints: Vec<serde_json::Value> = zip(values, times)
+            .map(|(value, time)| json!([value, time.and_utc().timestamp_millis()]))
+            .collect();
+        series.push(json!({"target": target.target, "datapoints": datapoints}));
+    }
+    Ok(axum::Json(series))
+}
+
+/// Logs matching the filter of a Grafana annotation query, latest first.
+pub async fn grafana_annotations_handler(
+    State(data): State<AppState>,
+    Json(query): Json<GrafanaAnnotationQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let filter_query = query.annotation["query"].as_str().unwrap_or_default();
+    let filter = filter::parse_filter(filter_query)?;
+    let logs = data
+        .db
+        .get_annotations(
+            query.range.from.naive_utc(),
+            query.range.to.naive_utc(),
+            &filter,
+            100,
+        )
+        .await?;
+    Ok(axum::Json(
+        logs.into_iter()
+            .map(|(time, level, source, logdata)| {
+                let text = match &logdata["message"] {
+                    serde_json::Value::String(message) => message.clone(),
+                    _ => logdata.to_string(),
+                };
+                let tags: Vec<String> = [Some(level.clone()), source]
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                json!({
+                    "annotation": query.annotation,
+                    "time": time.and_utc().timestamp_millis(),
+                    "title": level,
+                    "text": text,
+                    "tags": tags,
+                })
+            })
+            .collect::<Vec<serde_json::Value>>(),
+    ))
+}
+
 pub async fn list_views(State(data): State<AppState>) -> Result<impl IntoResponse, AppError> {
     let rows = data.db.list_filters().await?;
     Ok(axum::Json(
@@ -137,7 +563,6 @@ pub async fn list_views(State(data): State<AppState>) -> Result<impl IntoRespons
                 val.insert(
                     "cols".to_owned(),
                     zip(aggs, metrics)
-                        .into_iter()
                         .map(|(agg, metric)| json!({"metric": metric, "agg":agg}))
                         .collect(),
                 );
@@ -150,7 +575,9 @@ pub async fn list_views(State(data): State<AppState>) -> Result<impl IntoRespons
 #[cfg(test)]
 mod tests {
 
+    use crate::ingest::Ingest;
     use crate::repository::Repository;
+    use crate::tail::Tail;
 
     use super::{app, AppState};
     use axum::http::Request;
@@ -162,7 +589,9 @@ mod tests {
     #[sqlx::test]
     async fn test_health(pool: sqlx::PgPool) {
         let app = app().with_state(AppState {
-            db: Repository { pool: pool },
+            db: Repository { pool },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
         });
         let resp = app
             .oneshot(
@@ -179,7 +608,9 @@ mod tests {
     #[sqlx::test]
     async fn test_list_view(pool: sqlx::PgPool) {
         let app = app().with_state(AppState {
-            db: Repository { pool: pool },
+            db: Repository { pool },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
         });
         let resp = app
             .oneshot(
@@ -204,7 +635,9 @@ mod tests {
     #[sqlx::test]
     async fn test_list_metric(pool: sqlx::PgPool) {
         let app = app().with_state(AppState {
-            db: Repository { pool: pool },
+            db: Repository { pool },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
         });
         let resp = app
             .oneshot(
@@ -225,7 +658,9 @@ mod tests {
     #[sqlx::test]
     async fn test_post_get_metric(pool: sqlx::PgPool) {
         let app = app().with_state(AppState {
-            db: Repository { pool: pool },
+            db: Repository { pool },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
         });
         let send_body = json!({"start": chrono::DateTime::from_timestamp(1711302824, 0),
         "end": chrono::DateTime::from_timestamp(1711302888, 0),
@@ -246,7 +681,7 @@ mod tests {
         assert_eq!(resp.status(), 200);
 
         let body = resp.into_body().collect().await.unwrap().to_bytes();
-        let expected = Vec::from_iter(std::iter::repeat(serde_json::Value::Null).take(120));
+        let expected = vec![serde_json::Value::Null; 120];
         assert_eq!(
             serde_json::from_str::<Vec<serde_json::Value>>(
                 String::from_utf8(body.to_vec()).unwrap().as_str()
@@ -260,12 +695,15 @@ mod tests {
     async fn test_create_view(pool: sqlx::PgPool) {
         let app = app().with_state(AppState {
             db: Repository { pool: pool.clone() },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
         });
         let send_body = json!({
             "columns": [{"name": "test_col", "query": "logdata", "metric_agg": "max"}],
             "filter": {"name": "test_view", "query": "true"}})
         .to_string();
         let resp = app
+            .clone()
             .oneshot(
                 Request::builder()
                     .uri("/api/view")
@@ -312,5 +750,1047 @@ mod tests {
                 )
             ]
         );
+        assert_eq!(
+            sqlx::query(
+                "SELECT view_name::text FROM timescaledb_information.continuous_aggregates
+                    WHERE view_name LIKE 'test_view%' ORDER BY view_name",
+            )
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| row.get::<String, _>(0))
+            .collect::<Vec<String>>(),
+            vec!["test_view_min_count", "test_view_sec_count"]
+        );
+
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/view/test_view")
+                    .header("Content-Type", "application/json")
+                    .method("PATCH")
+                    .body(json!({"name": "errors", "query": "level = \"ERROR\""}).to_string())
+                    .unwrap(),
+            )
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            sqlx::query(
+                "SELECT view_name::text FROM timescaledb_information.continuous_aggregates
+                    ORDER BY view_name",
+            )
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| row.get::<String, _>(0))
+            .collect::<Vec<String>>(),
+            vec!["errors_min_count", "errors_sec_count"]
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_create_view_invalid_names(pool: sqlx::PgPool) {
+        let app = app().with_state(AppState {
+            db: Repository { pool: pool.clone() },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
+        });
+        for (columns, view_name, message) in [
+            (
+                json!([{"name": "test_col", "query": "logdata", "metric_agg": "max"}]),
+                "x; DROP TABLE logs",
+                "invalid view name",
+            ),
+            (
+                json!([{"name": "test_col", "query": "logdata", "metric_agg": "pg_sleep"}]),
+                "test_view",
+                "invalid metric_agg",
+            ),
+            (
+                json!([{"name": "a/b", "query": "logdata", "metric_agg": ""}]),
+                "test_view",
+                "invalid column name",
+            ),
+            (
+                json!([
+                    {"name": "test_col", "query": "logdata", "metric_agg": ""},
+                    {"name": "test_col", "query": "logdata.cpu", "metric_agg": "avg"}
+                ]),
+                "test_view",
+                "column test_col is listed twice",
+            ),
+            (json!([]), "test_view", "a view needs at least one column"),
+        ] {
+            let send_body =
+                json!({"columns": columns, "filter": {"name": view_name, "query": ""}}).to_string();
+            let resp = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri("/api/view")
+                        .header("Content-Type", "application/json")
+                        .method("POST")
+                        .body(send_body)
+                        .unwrap(),
+                )
+                .await
+                .expect("Request should not fail");
+            assert_eq!(resp.status(), 400);
+            let body = resp.into_body().collect().await.unwrap().to_bytes();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert!(
+                body["message"].as_str().unwrap().starts_with(message),
+                "{}",
+                body
+            );
+        }
+        assert_eq!(
+            sqlx::query("SELECT name FROM cols")
+                .fetch_all(&pool)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_create_view_invalid_filter(pool: sqlx::PgPool) {
+        let app = app().with_state(AppState {
+            db: Repository { pool: pool.clone() },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
+        });
+        let send_body = json!({
+            "columns": [{"name": "test_col", "query": "logdata", "metric_agg": "max"}],
+            "filter": {"name": "test_view", "query": "level = 'ERROR'; DROP TABLE logs"}})
+        .to_string();
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/view")
+                    .header("Content-Type", "application/json")
+                    .method("POST")
+                    .body(send_body)
+                    .unwrap(),
+            )
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 400);
+
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "bad_request");
+        assert_eq!(
+            body["message"],
+            "parse error at position 15: unexpected character ';'"
+        );
+        assert_eq!(
+            sqlx::query("SELECT name FROM filters WHERE name = 'test_view'")
+                .fetch_all(&pool)
+                .await
+                .unwrap()
+                .len(),
+            0
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_invalid_queries(pool: sqlx::PgPool) {
+        sqlx::raw_sql(
+            "INSERT INTO filters (name, query) VALUES ('legacy', $$logdata->>'level' = 'ERROR'$$), ('errors', 'level = \"ERROR\"');
+            INSERT INTO cols (name, query) VALUES ('cpu', 'logdata.cpu'), ('old_cpu', $$(logdata->>'cpu')::float$$);",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let db = Repository { pool };
+        let invalid: Vec<(&str, String)> = db
+            .invalid_queries()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(kind, name, _, _)| (kind, name))
+            .collect();
+        assert_eq!(
+            invalid,
+            [
+                ("view", "legacy".to_owned()),
+                ("column", "old_cpu".to_owned())
+            ]
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_delete_view(pool: sqlx::PgPool) {
+        sqlx::raw_sql(
+            "INSERT INTO filters (name, query) VALUES ('errors', 'level = \"ERROR\"');
+            INSERT INTO cols (name, query, metric_agg) VALUES ('cpu', 'logdata.cpu', 'avg');
+            INSERT INTO column_filter (column_name, filter_name, idx) VALUES ('Data', 'errors', 0), ('cpu', 'errors', 1);",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let app = app().with_state(AppState {
+            db: Repository { pool: pool.clone() },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
+        });
+        let delete = |uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .method("DELETE")
+                .body(String::new())
+                .unwrap()
+        };
+
+        let resp = app
+            .clone()
+            .oneshot(delete("/api/view/errors"))
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 200);
+        let names = |query: &'static str| {
+            let pool = pool.clone();
+            async move {
+                sqlx::query(query)
+                    .fetch_all(&pool)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|row| row.get::<String, _>(0))
+                    .collect::<Vec<String>>()
+            }
+        };
+        assert_eq!(names("SELECT name FROM filters").await, ["logs"]);
+        assert_eq!(
+            names("SELECT filter_name FROM column_filter").await,
+            ["logs"]
+        );
+        assert_eq!(names("SELECT name FROM cols").await, ["Data"]);
+
+        let resp = app
+            .clone()
+            .oneshot(delete("/api/view/errors"))
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 404);
+        let resp = app
+            .oneshot(delete("/api/view/logs"))
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 400);
+        assert_eq!(names("SELECT name FROM filters").await, ["logs"]);
+    }
+
+    #[sqlx::test]
+    async fn test_view_crud(pool: sqlx::PgPool) {
+        sqlx::raw_sql(
+            "INSERT INTO filters (name, query) VALUES ('errors', 'level = \"ERROR\"');
+            INSERT INTO cols (name, query, metric_agg) VALUES ('cpu', 'logdata.cpu', 'avg');
+            INSERT INTO column_filter (column_name, filter_name, idx) VALUES ('Data', 'errors', 0);",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let app = app().with_state(AppState {
+            db: Repository { pool },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
+        });
+        let request = |method: &str, uri: &str, body: serde_json::Value| {
+            Request::builder()
+                .uri(uri)
+                .header("Content-Type", "application/json")
+                .method(method)
+                .body(body.to_string())
+                .unwrap()
+        };
+
+        let resp = app
+            .clone()
+            .oneshot(request("GET", "/api/view/errors", json!(null)))
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 200);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "columns": [{"name": "Data", "query": "logdata", "metric_agg": "max"}],
+                "filter": {"name": "errors", "query": "level = \"ERROR\""},
+            })
+        );
+
+        let resp = app
+            .clone()
+            .oneshot(request(
+                "PATCH",
+                "/api/view/errors",
+                json!({"name": "failures", "columns": ["cpu", "Data"]}),
+            ))
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 200);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["filter"]["name"], "failures");
+        assert_eq!(body["columns"][0]["name"], "cpu");
+        assert_eq!(body["columns"][1]["name"], "Data");
+
+        for (method, uri, body, status) in [
+            ("GET", "/api/view/errors", json!(null), 404),
+            ("PATCH", "/api/view/failures", json!({"name": "logs"}), 409),
+            ("PATCH", "/api/view/logs", json!({"name": "all"}), 400),
+            (
+                "PATCH",
+                "/api/view/failures",
+                json!({"columns": ["nope"]}),
+                400,
+            ),
+            (
+                "PATCH",
+                "/api/view/failures",
+                json!({"query": "level ="}),
+                400,
+            ),
+        ] {
+            let resp = app
+                .clone()
+                .oneshot(request(method, uri, body.clone()))
+                .await
+                .expect("Request should not fail");
+            assert_eq!(resp.status(), status, "{} {} {}", method, uri, body);
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_view_versions(pool: sqlx::PgPool) {
+        sqlx::raw_sql(
+            "INSERT INTO filters (name, query) VALUES ('errors', 'level = \"ERROR\"');
+            INSERT INTO cols (name, query, metric_agg) VALUES ('cpu', 'logdata.cpu', 'avg');
+            INSERT INTO column_filter (column_name, filter_name, idx) VALUES ('Data', 'errors', 0);",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let app = app().with_state(AppState {
+            db: Repository { pool },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
+        });
+        let request = |method: &str, uri: &str, user: &str, body: serde_json::Value| {
+            Request::builder()
+                .uri(uri)
+                .header("Content-Type", "application/json")
+                .header("X-Forwarded-User", user)
+                .method(method)
+                .body(body.to_string())
+                .unwrap()
+        };
+        let get_json = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let resp = app
+                    .oneshot(request("GET", uri, "", json!(null)))
+                    .await
+                    .expect("Request should not fail");
+                let status = resp.status();
+                let body = resp.into_body().collect().await.unwrap().to_bytes();
+                (
+                    status,
+                    serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+                )
+            }
+        };
+
+        for (method, uri, user, body) in [
+            (
+                "PATCH",
+                "/api/view/errors",
+                "alice",
+                json!({"columns": ["cpu", "Data"]}),
+            ),
+            (
+                "PATCH",
+                "/api/column/cpu",
+                "bob",
+                json!({"metric_agg": "max"}),
+            ),
+        ] {
+            let resp = app
+                .clone()
+                .oneshot(request(method, uri, user, body))
+                .await
+                .expect("Request should not fail");
+            assert_eq!(resp.status(), 200);
+        }
+
+        let (status, versions) = get_json("/api/view/errors/versions").await;
+        assert_eq!(status, 200);
+        let summary: Vec<(i64, serde_json::Value, usize)> = versions
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|version| {
+                (
+                    version["version"].as_i64().unwrap(),
+                    version["author"].clone(),
+                    version["columns"].as_array().unwrap().len(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (3, json!("bob"), 2),
+                (2, json!("alice"), 2),
+                (1, json!(null), 1)
+            ]
+        );
+
+        let (status, diff) = get_json("/api/view/errors/diff?from=1&to=3").await;
+        assert_eq!(status, 200);
+        assert_eq!(
+            diff,
+            json!({
+                "from": 1,
+                "to": 3,
+                "query": null,
+                "columns": {"from": ["Data"], "to": ["cpu", "Data"]},
+                "added": [{"name": "cpu", "query": "logdata.cpu", "metric_agg": "max"}],
+                "removed": [],
+                "changed": [],
+            })
+        );
+
+        let resp = app
+            .clone()
+            .oneshot(request(
+                "POST",
+                "/api/view/errors/rollback",
+                "carol",
+                json!({"version": 1}),
+            ))
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 200);
+        let (_, view) = get_json("/api/view/errors").await;
+        assert_eq!(
+            view["columns"],
+            json!([{"name": "Data", "query": "logdata", "metric_agg": "max"}])
+        );
+        let (_, versions) = get_json("/api/view/errors/versions").await;
+        assert_eq!(versions[0]["version"], 4);
+        assert_eq!(versions[0]["author"], "carol");
+
+        let (status, _) = get_json("/api/view/errors/diff?from=1&to=9").await;
+        assert_eq!(status, 404);
+        let (status, _) = get_json("/api/view/nope/versions").await;
+        assert_eq!(status, 404);
+    }
+
+    #[sqlx::test]
+    async fn test_delete_view_rollback(pool: sqlx::PgPool) {
+        sqlx::raw_sql(
+            "INSERT INTO filters (name, query) VALUES ('errors', 'level = \"ERROR\"');
+            INSERT INTO column_filter (column_name, filter_name, idx) VALUES ('Data', 'errors', 0);",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let app = app().with_state(AppState {
+            db: Repository { pool },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
+        });
+        let request = |method: &str, uri: &str, body: serde_json::Value| {
+            Request::builder()
+                .uri(uri)
+                .header("Content-Type", "application/json")
+                .method(method)
+                .body(body.to_string())
+                .unwrap()
+        };
+
+        let resp = app
+            .clone()
+            .oneshot(request("DELETE", "/api/view/errors", json!(null)))
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 200);
+        let resp = app
+            .clone()
+            .oneshot(request("GET", "/api/view/errors/versions", json!(null)))
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 200);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let versions: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(versions.as_array().unwrap().len(), 1);
+        assert_eq!(versions[0]["query"], "level = \"ERROR\"");
+
+        let resp = app
+            .clone()
+            .oneshot(request(
+                "POST",
+                "/api/view/errors/rollback",
+                json!({"version": 1}),
+            ))
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 200);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let view: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            view,
+            json!({
+                "columns": [{"name": "Data", "query": "logdata", "metric_agg": "max"}],
+                "filter": {"name": "errors", "query": "level = \"ERROR\""},
+            })
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_column_crud(pool: sqlx::PgPool) {
+        let app = app().with_state(AppState {
+            db: Repository { pool },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
+        });
+        let request = |method: &str, uri: &str, body: serde_json::Value| {
+            Request::builder()
+                .uri(uri)
+                .header("Content-Type", "application/json")
+                .method(method)
+                .body(body.to_string())
+                .unwrap()
+        };
+        let cpu = json!({"name": "cpu", "query": "logdata.cpu", "metric_agg": "avg"});
+
+        for (method, uri, body, status) in [
+            ("POST", "/api/column", cpu.clone(), 201),
+            ("POST", "/api/column", cpu.clone(), 409),
+            (
+                "POST",
+                "/api/column",
+                json!({"name": "bad", "query": "logdata.", "metric_agg": ""}),
+                400,
+            ),
+            (
+                "PATCH",
+                "/api/column/cpu",
+                json!({"name": "load", "metric_agg": "max"}),
+                200,
+            ),
+            ("GET", "/api/column/cpu", json!(null), 404),
+            ("DELETE", "/api/column/Data", json!(null), 409),
+        ] {
+            let resp = app
+                .clone()
+                .oneshot(request(method, uri, body.clone()))
+                .await
+                .expect("Request should not fail");
+            assert_eq!(resp.status(), status, "{} {} {}", method, uri, body);
+        }
+
+        let resp = app
+            .clone()
+            .oneshot(request("GET", "/api/column", json!(null)))
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 200);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!([
+                {"name": "Data", "query": "logdata", "metric_agg": "max"},
+                {"name": "load", "query": "logdata.cpu", "metric_agg": "max"},
+            ])
+        );
+
+        let resp = app
+            .clone()
+            .oneshot(request("DELETE", "/api/column/load", json!(null)))
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 200);
+        let resp = app
+            .oneshot(request("DELETE", "/api/column/load", json!(null)))
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[sqlx::test]
+    async fn test_search(pool: sqlx::PgPool) {
+        sqlx::query(
+            "INSERT INTO logs (time, level, words, logdata) VALUES
+                ('2024-03-24 17:54:00', 'INFO', ARRAY['host', 'web-1', 'timeout'], '{\"host\": \"web-1\"}'),
+                ('2024-03-24 17:54:10', 'ERROR', ARRAY['host', 'web-2'], '{\"host\": \"web-2\"}'),
+                ('2024-03-24 17:54:20', 'INFO', ARRAY['host', 'web-1'], '{\"host\": \"web-1\"}')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let app = app().with_state(AppState {
+            db: Repository { pool },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
+        });
+        let send_body = json!({"start": chrono::DateTime::from_timestamp(1711302824, 0),
+        "end": chrono::DateTime::from_timestamp(1711302888, 0),
+        "terms": ["web-1 timeout"],
+        "mode": "any"})
+        .to_string();
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/search")
+                    .header("Content-Type", "application/json")
+                    .method("POST")
+                    .body(send_body)
+                    .unwrap(),
+            )
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 200);
+
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["logs"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            body["density"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|v| v.as_i64().unwrap())
+                .sum::<i64>(),
+            2
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_search_without_terms(pool: sqlx::PgPool) {
+        let app = app().with_state(AppState {
+            db: Repository { pool },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
+        });
+        let send_body = json!({"start": chrono::DateTime::from_timestamp(1711302824, 0),
+        "end": chrono::DateTime::from_timestamp(1711302888, 0),
+        "terms": [" "]})
+        .to_string();
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/search")
+                    .header("Content-Type", "application/json")
+                    .method("POST")
+                    .body(send_body)
+                    .unwrap(),
+            )
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 400);
+    }
+
+    #[sqlx::test]
+    async fn test_ingest(pool: sqlx::PgPool) {
+        let app = app().with_state(AppState {
+            db: Repository { pool: pool.clone() },
+            tail: Tail::default(),
+            ingest: Ingest::new(200, vec!["time".to_owned()]),
+        });
+        let ingest_request = |body: &str| {
+            Request::builder()
+                .uri("/api/ingest")
+                .header("Content-Type", "application/x-ndjson")
+                .method("POST")
+                .body(body.to_owned())
+                .unwrap()
+        };
+        let resp = app
+            .clone()
+            .oneshot(ingest_request(
+                "{\"level\": \"ERROR\", \"msg\": \"disk full\", \"source\": \"web-1\", \"time\": 1711302824}\n\
+                 {\"msg\": \"say \\\"hi\\\"\"}\n\
+                 not json\n",
+            ))
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 200);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({"accepted": 2, "rejected": 1}));
+
+        let rows = sqlx::query(
+            "SELECT time, level, source, words, logdata FROM logs ORDER BY level, time",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].get::<String, _>(1), "ERROR");
+        assert_eq!(
+            rows[0].get::<chrono::NaiveDateTime, _>(0),
+            chrono::DateTime::from_timestamp(1711302824, 0)
+                .unwrap()
+                .naive_utc()
+        );
+        assert_eq!(
+            rows[0].get::<Option<String>, _>(2).as_deref(),
+            Some("web-1")
+        );
+        let mut words = rows[0].get::<Vec<String>, _>(3);
+        words.sort();
+        assert_eq!(words, ["disk", "full", "msg", "source", "time", "web-1"]);
+        assert_eq!(rows[1].get::<Option<String>, _>(2), None);
+        assert_eq!(
+            rows[1].get::<serde_json::Value, _>(4),
+            json!({"msg": "say \"hi\""})
+        );
+        assert_eq!(
+            sqlx::query("SELECT reason FROM rejected_logs")
+                .fetch_all(&pool)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let resp = app
+            .oneshot(ingest_request(&format!("[{}]", vec!["{}"; 150].join(","))))
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 413);
+    }
+
+    #[sqlx::test]
+    async fn test_otlp_logs(pool: sqlx::PgPool) {
+        let app = app().with_state(AppState {
+            db: Repository { pool: pool.clone() },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
+        });
+        let send_body = json!({"resourceLogs": [{
+            "resource": {"attributes": [{"key": "service.name", "value": {"stringValue": "api"}}]},
+            "scopeLogs": [{"logRecords": [
+                {"severityNumber": 9, "body": {"stringValue": "started"}, "traceId": "5b8efff798038103d269b633813fc60c"},
+                {"body": {"stringValue": "a\u{0}b"}}
+            ]}]
+        }]})
+        .to_string();
+        let resp = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/v1/logs")
+                    .header("Content-Type", "application/json")
+                    .method("POST")
+                    .body(send_body)
+                    .unwrap(),
+            )
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 200);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["partialSuccess"]["rejectedLogRecords"], "1");
+
+        let row = sqlx::query("SELECT level, source FROM logs WHERE 'started' = ANY(words)")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(row.get::<String, _>(0), "INFO");
+        assert_eq!(row.get::<String, _>(1), "api");
+        assert_eq!(
+            sqlx::query(
+                "SELECT id FROM logs WHERE logdata @> '{\"trace_id\": \"5b8efff798038103d269b633813fc60c\"}'"
+            )
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .len(),
+            1
+        );
+
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .uri("/v1/logs")
+                    .header("Content-Type", "application/x-protobuf")
+                    .method("POST")
+                    .body("not protobuf".to_owned())
+                    .unwrap(),
+            )
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 400);
+    }
+
+    #[sqlx::test]
+    async fn test_grafana(pool: sqlx::PgPool) {
+        sqlx::query(
+            "INSERT INTO logs (time, level, source, logdata) VALUES
+                ('2024-03-24 17:54:00', 'INFO', 'web-1', '{\"message\": \"started\"}'),
+                ('2024-03-24 17:54:10', 'ERROR', 'web-1', '{\"message\": \"disk full\"}')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let app = app().with_state(AppState {
+            db: Repository { pool },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
+        });
+        let post = |uri: &str, body: serde_json::Value| {
+            Request::builder()
+                .uri(uri)
+                .header("Content-Type", "application/json")
+                .method("POST")
+                .body(body.to_string())
+                .unwrap()
+        };
+        let range = json!({"from": "2024-03-24T17:53:44Z", "to": "2024-03-24T17:54:48Z"});
+
+        let resp = app
+            .clone()
+            .oneshot(post("/api/grafana/search", json!({"target": ""})))
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 200);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"[\"logs\",\"logs/Data\"]");
+
+        let resp = app
+            .clone()
+            .oneshot(post(
+                "/api/grafana/annotations",
+                json!({"range": range, "annotation": {"name": "errors", "query": "level = \"ERROR\""}}),
+            ))
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 200);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!([{
+                "annotation": {"name": "errors", "query": "level = \"ERROR\""},
+                "time": 1711302850000_i64,
+                "title": "ERROR",
+                "text": "disk full",
+                "tags": ["ERROR", "web-1"],
+            }])
+        );
+
+        let resp = app
+            .clone()
+            .oneshot(post(
+                "/api/column",
+                json!({"name": "cpu", "query": "logdata.cpu", "metric_agg": "avg"}),
+            ))
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 201);
+        // Unknown views, and columns the view does not have, are not found
+        for target in ["nope", "nope/Data", "logs/cpu", "logs/nope"] {
+            let resp = app
+                .clone()
+                .oneshot(post(
+                    "/api/grafana/query",
+                    json!({"range": range, "targets": [{"target": target, "refId": "A"}]}),
+                ))
+                .await
+                .expect("Request should not fail");
+            assert_eq!(resp.status(), 404, "{}", target);
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_errors(pool: sqlx::PgPool) {
+        let app = app().with_state(AppState {
+            db: Repository { pool },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
+        });
+        let send_body = json!({"start": chrono::DateTime::from_timestamp(1711302824, 0),
+        "end": chrono::DateTime::from_timestamp(1711302888, 0),
+        "table": "nope"})
+        .to_string();
+        let resp = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/logs")
+                    .header("Content-Type", "application/json")
+                    .header("x-request-id", "req-42")
+                    .method("POST")
+                    .body(send_body)
+                    .unwrap(),
+            )
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 404);
+        assert_eq!(resp.headers()["x-request-id"], "req-42");
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({"code": "not_found", "message": "view nope not found", "request_id": "req-42"})
+        );
+
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/logs")
+                    .header("Content-Type", "application/json")
+                    .method("POST")
+                    .body("{\"start\": ".to_owned())
+                    .unwrap(),
+            )
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 400);
+        let request_id = resp.headers()["x-request-id"].to_str().unwrap().to_owned();
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "bad_request");
+        assert_eq!(body["request_id"], request_id);
+    }
+
+    #[sqlx::test]
+    async fn test_logs_pagination(pool: sqlx::PgPool) {
+        sqlx::query(
+            "INSERT INTO logs (time, level, logdata) VALUES
+                ('2024-03-24 17:54:00', 'INFO', '{\"n\": 1}'),
+                ('2024-03-24 17:54:00', 'INFO', '{\"n\": 2}'),
+                ('2024-03-24 17:54:10', 'INFO', '{\"n\": 3}')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let app = app().with_state(AppState {
+            db: Repository { pool },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
+        });
+        let mut cursor = serde_json::Value::Null;
+        let mut seen = Vec::new();
+        for _ in 0..2 {
+            let send_body = json!({"start": chrono::DateTime::from_timestamp(1711302824, 0),
+            "end": chrono::DateTime::from_timestamp(1711302888, 0),
+            "order": "asc",
+            "limit": 2,
+            "cursor": cursor})
+            .to_string();
+            let resp = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri("/api/logs")
+                        .header("Content-Type", "application/json")
+                        .method("POST")
+                        .body(send_body)
+                        .unwrap(),
+                )
+                .await
+                .expect("Request should not fail");
+            assert_eq!(resp.status(), 200);
+            let body = resp.into_body().collect().await.unwrap().to_bytes();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            for log in body["logs"].as_array().unwrap() {
+                seen.push(log[2][0]["n"].as_i64().unwrap());
+            }
+            cursor = body["next_cursor"].clone();
+        }
+        assert_eq!(seen, vec![1, 2, 3]);
+        assert_eq!(cursor, serde_json::Value::Null);
+    }
+
+    #[sqlx::test]
+    async fn test_logs_invalid_cursor(pool: sqlx::PgPool) {
+        let app = app().with_state(AppState {
+            db: Repository { pool },
+            tail: Tail::default(),
+            ingest: Ingest::default(),
+        });
+        let send_body = json!({"start": chrono::DateTime::from_timestamp(1711302824, 0),
+        "end": chrono::DateTime::from_timestamp(1711302888, 0),
+        "cursor": "not-a-cursor"})
+        .to_string();
+        let resp = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/logs")
+                    .header("Content-Type", "application/json")
+                    .method("POST")
+                    .body(send_body)
+                    .unwrap(),
+            )
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 400);
+    }
+
+    #[sqlx::test]
+    async fn test_tail(pool: sqlx::PgPool) {
+        let app = app().with_state(AppState {
+            db: Repository { pool: pool.clone() },
+            tail: Tail::new(1, std::time::Duration::from_millis(10)),
+            ingest: Ingest::default(),
+        });
+        let tail_request = || {
+            Request::builder()
+                .uri("/api/tail/logs?terms=web-1")
+                .method("GET")
+                .body("".to_owned())
+                .unwrap()
+        };
+        let resp = app
+            .clone()
+            .oneshot(tail_request())
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 200);
+
+        let busy = app
+            .clone()
+            .oneshot(tail_request())
+            .await
+            .expect("Request should not fail");
+        assert_eq!(busy.status(), 503);
+
+        sqlx::query(
+            "INSERT INTO logs (time, level, words, logdata) VALUES
+                ('2024-03-24 17:54:00', 'INFO', ARRAY['web-2'], '{\"host\": \"web-2\"}'),
+                ('2024-03-24 17:54:10', 'ERROR', ARRAY['web-1'], '{\"host\": \"web-1\"}')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let mut body = resp.into_body();
+        let frame = tokio::time::timeout(std::time::Duration::from_secs(5), body.frame())
+            .await
+            .expect("A log should be streamed")
+            .unwrap()
+            .unwrap();
+        let event = String::from_utf8(frame.into_data().unwrap().to_vec()).unwrap();
+        assert!(event.starts_with("event: log\n"));
+        assert!(event.contains("\"host\":\"web-1\""));
+        assert!(!event.contains("web-2"));
+        drop(body);
+
+        let resp = app
+            .oneshot(tail_request())
+            .await
+            .expect("Request should not fail");
+        assert_eq!(resp.status(), 200);
     }
 }
@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use logdog_row::DEFAULT_TIME_FIELDS;
+pub use logdog_row::{LogRow, Rejected};
+
+/// Limits and settings of `/api/ingest`.
+#[derive(Clone)]
+pub struct Ingest {
+    pub max_bytes: usize,
+    /// Fields holding the event time, the first that parses wins
+    pub time_fields: Arc<Vec<String>>,
+}
+
+impl Ingest {
+    pub fn new(max_bytes: usize, time_fields: Vec<String>) -> Self {
+        Self {
+            max_bytes,
+            time_fields: Arc::new(time_fields),
+        }
+    }
+}
+
+impl Default for Ingest {
+    fn default() -> Self {
+        Self::new(
+            16 * 1024 * 1024,
+            DEFAULT_TIME_FIELDS.map(str::to_owned).to_vec(),
+        )
+    }
+}
+
+/// Split a request body into storable rows and rejected records.
+///
+/// The body is a JSON array of objects, or NDJSON: one object per line,
+/// blank lines being skipped. A body starting with `[` that is not valid
+/// JSON is an error, as nothing of it can be read.
+pub fn parse_body(
+    body: &str,
+    time_fields: &[String],
+) -> Result<(Vec<LogRow>, Vec<Rejected>), String> {
+    let values: Vec<Result<serde_json::Value, Rejected>> = if body.trim_start().starts_with('[') {
+        serde_json::from_str::<Vec<serde_json::Value>>(body)
+            .map_err(|err| format!("invalid JSON array: {}", err))?
+            .into_iter()
+            .map(Ok)
+            .collect()
+    } else {
+        body.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line)
+                    .map_err(|err| Rejected::new(format!("invalid JSON: {}", err), line))
+            })
+            .collect()
+    };
+    let mut rows = Vec::new();
+    let mut rejected = Vec::new();
+    for value in values {
+        let value = match value {
+            Ok(value) => value,
+            Err(rejection) => {
+                rejected.push(rejection);
+                continue;
+            }
+        };
+        let Some(data) = value.as_object() else {
+            rejected.push(Rejected::new("row is not a JSON object", value.to_string()));
+            continue;
+        };
+        match LogRow::new(data, time_fields) {
+            Ok(row) => rows.push(row),
+            Err(reason) => rejected.push(Rejected::new(reason, value.to_string())),
+        }
+    }
+    Ok((rows, rejected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_parse_body() {
+        let fields = Ingest::default().time_fields;
+        let (rows, rejected) = parse_body(
+            "{\"level\": \"ERROR\", \"msg\": \"disk-full on web_1\", \"time\": 1711302824}\n\n\
+             {\"msg\": \n[1]\n{\"level\": 2}\n",
+            &fields,
+        )
+        .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].level, "ERROR");
+        assert_eq!(
+            rows[0].time,
+            Utc.with_ymd_and_hms(2024, 3, 24, 17, 53, 44).unwrap()
+        );
+        let mut words = rows[0].words.clone();
+        words.sort();
+        assert_eq!(words, ["disk-full", "msg", "on", "time", "web_1"]);
+        assert!(rejected[0].reason.starts_with("invalid JSON"));
+        assert_eq!(
+            rejected[1..],
+            [
+                Rejected::new("row is not a JSON object", "[1]"),
+                Rejected::new("level is not a string: 2", r#"{"level":2}"#),
+            ]
+        );
+
+        let (rows, rejected) = parse_body(
+            r#"[{"msg": "a", "source": "web-1"}, {"msg": "b"}]"#,
+            &fields,
+        )
+        .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].source.as_deref(), Some("web-1"));
+        assert_eq!(rows[1].level, "INFO");
+        assert!(rejected.is_empty());
+
+        assert!(parse_body("[{\"msg\": ", &fields).is_err());
+    }
+}
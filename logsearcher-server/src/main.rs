@@ -1,21 +1,31 @@
 mod config;
 mod errors;
+mod filter;
 mod handler;
+mod ingest;
 mod model;
+mod otlp;
 mod repository;
+mod tail;
 
 use crate::config::Config;
+use crate::errors::REQUEST_ID;
+use crate::ingest::Ingest;
 use crate::repository::Repository;
+use crate::tail::Tail;
 use axum::http::{
     header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
     HeaderValue, Method,
 };
 use dotenv::dotenv;
+use std::time::Duration;
 use tower_http::cors::CorsLayer;
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: Repository,
+    pub tail: Tail,
+    pub ingest: Ingest,
 }
 use crate::handler::app;
 
@@ -28,11 +38,33 @@ async fn main() {
         .allow_origin("http://localhost:8000".parse::<HeaderValue>().unwrap())
         .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
         .allow_credentials(true)
-        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE]);
+        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE, REQUEST_ID])
+        .expose_headers([REQUEST_ID]);
 
+    let db = Repository::connect(config.pg_url.as_str()).await;
+    // Queries saved as raw SQL before the filter language fail until rewritten
+    match db.invalid_queries().await {
+        Ok(invalid) => {
+            for (kind, name, query, error) in invalid {
+                tracing::warn!(
+                    message = "query no longer parses, rewrite it in the filter language",
+                    kind,
+                    name,
+                    query,
+                    error
+                );
+            }
+        }
+        Err(err) => tracing::error!(message = "checking the saved queries failed", error = ?err),
+    }
     let app = app()
         .with_state(AppState {
-            db: Repository::connect(config.pg_url.as_str()).await,
+            db,
+            tail: Tail::new(
+                config.tail_max_clients,
+                Duration::from_millis(config.tail_poll_millis),
+            ),
+            ingest: Ingest::new(config.ingest_max_bytes, config.time_fields),
         })
         .layer(cors);
     // run our app with hyper, listening globally on port 3000
@@ -1,18 +1,91 @@
-use chrono::Utc;
+use chrono::{NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::errors::AppError;
+use crate::filter::WordMatch;
+
+pub const MAX_PAGE_SIZE: i64 = 1000;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct LogQuery {
     pub start: chrono::DateTime<Utc>,
     pub end: chrono::DateTime<Utc>,
     #[serde(default = "default_table")]
     pub table: String,
-    #[serde(default = "default_offset")]
-    pub offset: i64,
+    #[serde(flatten)]
+    pub page: PageQuery,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PageQuery {
+    #[serde(default)]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub order: SortOrder,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+fn default_limit() -> i64 {
+    40
+}
+
+impl PageQuery {
+    pub fn cursor(&self) -> Result<Option<Cursor>, AppError> {
+        match &self.cursor {
+            None => Ok(None),
+            Some(cursor) => Cursor::decode(cursor)
+                .map(Some)
+                .ok_or_else(|| AppError::BadRequest("invalid cursor".to_owned())),
+        }
+    }
+
+    pub fn page_size(&self) -> i64 {
+        self.limit.clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+/// Position of the last log of a page, as (time, row id).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cursor {
+    pub time: NaiveDateTime,
+    pub id: i64,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        format!(
+            "{:016x}{:016x}",
+            self.time.and_utc().timestamp_micros() as u64,
+            self.id as u64
+        )
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        if cursor.len() != 32 || !cursor.is_ascii() {
+            return None;
+        }
+        let micros = u64::from_str_radix(&cursor[..16], 16).ok()? as i64;
+        let id = u64::from_str_radix(&cursor[16..], 16).ok()? as i64;
+        Some(Self {
+            time: chrono::DateTime::from_timestamp_micros(micros)?.naive_utc(),
+            id,
+        })
+    }
 }
 
-fn default_offset() -> i64 {
-    0
+#[derive(Debug, Serialize)]
+pub struct LogPage {
+    pub logs: Vec<(NaiveDateTime, String, Vec<serde_json::Value>)>,
+    pub next_cursor: Option<String>,
 }
 
 fn default_filter() -> String {
@@ -23,7 +96,7 @@ fn default_table() -> String {
     "logs".to_owned()
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct ColumnDef {
     pub name: String,
     pub query: String,
@@ -43,6 +116,116 @@ pub struct ViewQuery {
     pub filter: FilterDef,
 }
 
+/// Changes to a view, the fields left out are kept.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ViewPatch {
+    pub name: Option<String>,
+    pub query: Option<String>,
+    /// Names of existing columns, in display order
+    pub columns: Option<Vec<String>>,
+}
+
+/// Changes to a column, the fields left out are kept.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ColumnPatch {
+    pub name: Option<String>,
+    pub query: Option<String>,
+    pub metric_agg: Option<String>,
+}
+
+/// A stored definition of a view, kept each time it changes.
+#[derive(Debug, Clone, Serialize)]
+pub struct ViewVersion {
+    pub version: i32,
+    pub time: NaiveDateTime,
+    pub author: Option<String>,
+    pub query: String,
+    pub columns: Vec<ColumnDef>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DiffQuery {
+    pub from: i32,
+    pub to: i32,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RollbackQuery {
+    pub version: i32,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Change<T> {
+    pub from: T,
+    pub to: T,
+}
+
+/// Differences between two versions of a view, columns being matched by name.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ViewDiff {
+    pub from: i32,
+    pub to: i32,
+    /// Set when the filter changed
+    pub query: Option<Change<String>>,
+    /// Set when the column names or their order changed
+    pub columns: Option<Change<Vec<String>>>,
+    pub added: Vec<ColumnDef>,
+    pub removed: Vec<ColumnDef>,
+    pub changed: Vec<Change<ColumnDef>>,
+}
+
+impl ViewDiff {
+    pub fn new(from: &ViewVersion, to: &ViewVersion) -> Self {
+        let find = |columns: &[ColumnDef], name: &str| {
+            columns.iter().find(|column| column.name == name).cloned()
+        };
+        let names = |version: &ViewVersion| {
+            version
+                .columns
+                .iter()
+                .map(|column| column.name.clone())
+                .collect::<Vec<String>>()
+        };
+        let (from_names, to_names) = (names(from), names(to));
+        Self {
+            from: from.version,
+            to: to.version,
+            query: (from.query != to.query).then(|| Change {
+                from: from.query.clone(),
+                to: to.query.clone(),
+            }),
+            columns: (from_names != to_names).then_some(Change {
+                from: from_names,
+                to: to_names,
+            }),
+            added: to
+                .columns
+                .iter()
+                .filter(|column| find(&from.columns, &column.name).is_none())
+                .cloned()
+                .collect(),
+            removed: from
+                .columns
+                .iter()
+                .filter(|column| find(&to.columns, &column.name).is_none())
+                .cloned()
+                .collect(),
+            changed: from
+                .columns
+                .iter()
+                .filter_map(|old| {
+                    find(&to.columns, &old.name)
+                        .filter(|new| new != old)
+                        .map(|new| Change {
+                            from: old.clone(),
+                            to: new,
+                        })
+                })
+                .collect(),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct MetricQuery {
     pub start: chrono::DateTime<Utc>,
@@ -50,3 +233,59 @@ pub struct MetricQuery {
     pub metric_name: String,
     pub view_name: String,
 }
+
+/// Time range of a Grafana JSON data source request.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GrafanaRange {
+    pub from: chrono::DateTime<Utc>,
+    pub to: chrono::DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GrafanaSearch {
+    #[serde(default)]
+    pub target: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GrafanaTarget {
+    #[serde(default)]
+    pub target: String,
+    #[serde(default)]
+    pub hide: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GrafanaQuery {
+    pub range: GrafanaRange,
+    pub targets: Vec<GrafanaTarget>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GrafanaAnnotationQuery {
+    pub range: GrafanaRange,
+    /// Sent back with each annotation, its `query` is a filter
+    pub annotation: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TailQuery {
+    /// Space separated words, as in [`SearchQuery::terms`].
+    #[serde(default)]
+    pub terms: Option<String>,
+    #[serde(default)]
+    pub mode: WordMatch,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SearchQuery {
+    pub start: chrono::DateTime<Utc>,
+    pub end: chrono::DateTime<Utc>,
+    pub terms: Vec<String>,
+    #[serde(default)]
+    pub mode: WordMatch,
+    #[serde(default = "default_table")]
+    pub table: String,
+    #[serde(flatten)]
+    pub page: PageQuery,
+}
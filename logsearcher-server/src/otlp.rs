@@ -0,0 +1,491 @@
+//! OTLP/HTTP logs, in their protobuf or JSON encoding.
+//!
+//! Only the messages and fields logdog stores are declared, protobuf skips
+//! the others.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{SecondsFormat, TimeZone, Utc};
+use serde_json::{Map, Value};
+
+use crate::ingest::{LogRow, Rejected};
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExportLogsServiceRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub resource_logs: Vec<ResourceLogs>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExportLogsServiceResponse {
+    #[prost(message, optional, tag = "1")]
+    pub partial_success: Option<ExportLogsPartialSuccess>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExportLogsPartialSuccess {
+    #[prost(int64, tag = "1")]
+    pub rejected_log_records: i64,
+    #[prost(string, tag = "2")]
+    pub error_message: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ResourceLogs {
+    #[prost(message, optional, tag = "1")]
+    pub resource: Option<Resource>,
+    #[prost(message, repeated, tag = "2")]
+    pub scope_logs: Vec<ScopeLogs>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Resource {
+    #[prost(message, repeated, tag = "1")]
+    pub attributes: Vec<KeyValue>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ScopeLogs {
+    #[prost(message, optional, tag = "1")]
+    pub scope: Option<InstrumentationScope>,
+    #[prost(message, repeated, tag = "2")]
+    pub log_records: Vec<LogRecord>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct InstrumentationScope {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub version: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LogRecord {
+    #[prost(fixed64, tag = "1")]
+    pub time_unix_nano: u64,
+    #[prost(fixed64, tag = "11")]
+    pub observed_time_unix_nano: u64,
+    #[prost(int32, tag = "2")]
+    pub severity_number: i32,
+    #[prost(string, tag = "3")]
+    pub severity_text: String,
+    #[prost(message, optional, tag = "5")]
+    pub body: Option<AnyValue>,
+    #[prost(message, repeated, tag = "6")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(bytes = "vec", tag = "9")]
+    pub trace_id: Vec<u8>,
+    #[prost(bytes = "vec", tag = "10")]
+    pub span_id: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct KeyValue {
+    #[prost(string, tag = "1")]
+    pub key: String,
+    #[prost(message, optional, tag = "2")]
+    pub value: Option<AnyValue>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AnyValue {
+    #[prost(oneof = "any_value::Value", tags = "1, 2, 3, 4, 5, 6, 7")]
+    pub value: Option<any_value::Value>,
+}
+
+pub mod any_value {
+    // Variants are named after the fields of the OTLP oneof
+    #[allow(clippy::enum_variant_names)]
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Value {
+        #[prost(string, tag = "1")]
+        StringValue(String),
+        #[prost(bool, tag = "2")]
+        BoolValue(bool),
+        #[prost(int64, tag = "3")]
+        IntValue(i64),
+        #[prost(double, tag = "4")]
+        DoubleValue(f64),
+        #[prost(message, tag = "5")]
+        ArrayValue(super::ArrayValue),
+        #[prost(message, tag = "6")]
+        KvlistValue(super::KeyValueList),
+        #[prost(bytes, tag = "7")]
+        BytesValue(Vec<u8>),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ArrayValue {
+    #[prost(message, repeated, tag = "1")]
+    pub values: Vec<AnyValue>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct KeyValueList {
+    #[prost(message, repeated, tag = "1")]
+    pub values: Vec<KeyValue>,
+}
+
+/// Elements of the `key` array of a JSON object, none if it is missing.
+fn json_items<'a>(value: &'a Value, key: &str) -> impl Iterator<Item = &'a Value> {
+    value
+        .get(key)
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+}
+
+fn json_str(value: &Value, key: &str) -> String {
+    value
+        .get(key)
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_owned()
+}
+
+/// 64 bit integers are strings in OTLP JSON, but numbers are accepted too.
+fn json_int(value: Option<&Value>) -> Option<i64> {
+    match value? {
+        Value::String(text) => text.parse().ok(),
+        number => number.as_i64(),
+    }
+}
+
+fn hex_decode(text: &str) -> Vec<u8> {
+    (0..text.len() / 2)
+        .map_while(|i| u8::from_str_radix(text.get(2 * i..2 * i + 2)?, 16).ok())
+        .collect()
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn json_key_values(value: &Value, key: &str) -> Vec<KeyValue> {
+    json_items(value, key)
+        .map(|item| KeyValue {
+            key: json_str(item, "key"),
+            value: item.get("value").map(json_any_value),
+        })
+        .collect()
+}
+
+fn json_any_value(value: &Value) -> AnyValue {
+    use any_value::Value as V;
+    let value = if let Some(text) = value.get("stringValue").and_then(Value::as_str) {
+        Some(V::StringValue(text.to_owned()))
+    } else if let Some(flag) = value.get("boolValue").and_then(Value::as_bool) {
+        Some(V::BoolValue(flag))
+    } else if let Some(number) = json_int(value.get("intValue")) {
+        Some(V::IntValue(number))
+    } else if let Some(number) = value.get("doubleValue").and_then(Value::as_f64) {
+        Some(V::DoubleValue(number))
+    } else if let Some(array) = value.get("arrayValue") {
+        Some(V::ArrayValue(ArrayValue {
+            values: json_items(array, "values").map(json_any_value).collect(),
+        }))
+    } else if let Some(list) = value.get("kvlistValue") {
+        Some(V::KvlistValue(KeyValueList {
+            values: json_key_values(list, "values"),
+        }))
+    } else {
+        // Bytes are base64 in JSON, kept as a string when they do not decode
+        value
+            .get("bytesValue")
+            .and_then(Value::as_str)
+            .map(|text| match BASE64.decode(text) {
+                Ok(bytes) => V::BytesValue(bytes),
+                Err(_) => V::StringValue(text.to_owned()),
+            })
+    };
+    AnyValue { value }
+}
+
+/// Read the JSON encoding of an export request, whose field names are
+/// lowerCamelCase and trace and span ids hex strings.
+pub fn parse_json(body: &[u8]) -> Result<ExportLogsServiceRequest, String> {
+    let request: Value =
+        serde_json::from_slice(body).map_err(|err| format!("invalid JSON: {}", err))?;
+    if !request.is_object() {
+        return Err("request is not a JSON object".to_owned());
+    }
+    let resource_logs = json_items(&request, "resourceLogs")
+        .map(|resource_logs| ResourceLogs {
+            resource: resource_logs.get("resource").map(|resource| Resource {
+                attributes: json_key_values(resource, "attributes"),
+            }),
+            scope_logs: json_items(resource_logs, "scopeLogs")
+                .map(|scope_logs| ScopeLogs {
+                    scope: scope_logs.get("scope").map(|scope| InstrumentationScope {
+                        name: json_str(scope, "name"),
+                        version: json_str(scope, "version"),
+                    }),
+                    log_records: json_items(scope_logs, "logRecords")
+                        .map(|record| LogRecord {
+                            time_unix_nano: json_int(record.get("timeUnixNano")).unwrap_or(0)
+                                as u64,
+                            observed_time_unix_nano: json_int(record.get("observedTimeUnixNano"))
+                                .unwrap_or(0)
+                                as u64,
+                            severity_number: json_int(record.get("severityNumber")).unwrap_or(0)
+                                as i32,
+                            severity_text: json_str(record, "severityText"),
+                            body: record.get("body").map(json_any_value),
+                            attributes: json_key_values(record, "attributes"),
+                            trace_id: hex_decode(&json_str(record, "traceId")),
+                            span_id: hex_decode(&json_str(record, "spanId")),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        })
+        .collect();
+    Ok(ExportLogsServiceRequest { resource_logs })
+}
+
+fn to_json(value: &AnyValue) -> Value {
+    use any_value::Value as V;
+    match &value.value {
+        None => Value::Null,
+        Some(V::StringValue(text)) => text.as_str().into(),
+        Some(V::BoolValue(flag)) => (*flag).into(),
+        Some(V::IntValue(number)) => (*number).into(),
+        Some(V::DoubleValue(number)) => (*number).into(),
+        Some(V::ArrayValue(array)) => array.values.iter().map(to_json).collect(),
+        Some(V::KvlistValue(list)) => attributes_json(&list.values).into(),
+        Some(V::BytesValue(bytes)) => BASE64.encode(bytes).into(),
+    }
+}
+
+fn attributes_json(attributes: &[KeyValue]) -> Map<String, Value> {
+    attributes
+        .iter()
+        .map(|attribute| {
+            let value = attribute.value.as_ref().map_or(Value::Null, to_json);
+            (attribute.key.clone(), value)
+        })
+        .collect()
+}
+
+/// Map an OTLP severity to the levels logdog uses, from its number or else
+/// its text.
+fn severity_level(number: i32, text: &str) -> Option<String> {
+    let level = match number {
+        1..=4 => "TRACE",
+        5..=8 => "DEBUG",
+        9..=12 => "INFO",
+        13..=16 => "WARNING",
+        17..=20 => "ERROR",
+        21..=24 => "CRITICAL",
+        _ => match text.to_ascii_uppercase().as_str() {
+            "" => return None,
+            "WARN" => "WARNING",
+            "FATAL" => "CRITICAL",
+            other => return Some(other.to_owned()),
+        },
+    };
+    Some(level.to_owned())
+}
+
+/// Turn the records of an export request into rows: the body, as `message`,
+/// makes `logdata` with the record `attributes`, `trace_id`, `span_id`, the
+/// scope and the `resource` attributes. `service.name` becomes the `source`.
+/// Bytes values are base64, as in the JSON encoding.
+pub fn parse_request(request: &ExportLogsServiceRequest) -> (Vec<LogRow>, Vec<Rejected>) {
+    let time_fields = ["time".to_owned()];
+    let mut rows = Vec::new();
+    let mut rejected = Vec::new();
+    for resource_logs in &request.resource_logs {
+        let resource = resource_logs
+            .resource
+            .as_ref()
+            .map(|resource| attributes_json(&resource.attributes))
+            .unwrap_or_default();
+        for scope_logs in &resource_logs.scope_logs {
+            let scope = scope_logs
+                .scope
+                .as_ref()
+                .map(|scope| scope.name.as_str())
+                .filter(|name| !name.is_empty());
+            for record in &scope_logs.log_records {
+                let mut data = Map::new();
+                if let Some(body) = &record.body {
+                    data.insert("message".to_owned(), to_json(body));
+                }
+                if let Some(level) = severity_level(record.severity_number, &record.severity_text) {
+                    data.insert("level".to_owned(), level.into());
+                }
+                if let Some(source) = resource.get("service.name").filter(|name| name.is_string()) {
+                    data.insert("source".to_owned(), source.clone());
+                }
+                let time = [record.time_unix_nano, record.observed_time_unix_nano]
+                    .into_iter()
+                    .find(|nanos| *nanos > 0 && *nanos <= i64::MAX as u64);
+                if let Some(nanos) = time {
+                    let time = Utc.timestamp_nanos(nanos as i64);
+                    data.insert(
+                        "time".to_owned(),
+                        time.to_rfc3339_opts(SecondsFormat::AutoSi, true).into(),
+                    );
+                }
+                if !record.trace_id.is_empty() {
+                    data.insert("trace_id".to_owned(), hex_encode(&record.trace_id).into());
+                }
+                if !record.span_id.is_empty() {
+                    data.insert("span_id".to_owned(), hex_encode(&record.span_id).into());
+                }
+                if let Some(scope) = scope {
+                    data.insert("scope".to_owned(), scope.into());
+                }
+                if !resource.is_empty() {
+                    data.insert("resource".to_owned(), resource.clone().into());
+                }
+                if !record.attributes.is_empty() {
+                    data.insert(
+                        "attributes".to_owned(),
+                        attributes_json(&record.attributes).into(),
+                    );
+                }
+                match LogRow::new(&data, &time_fields) {
+                    Ok(row) => rows.push(row),
+                    Err(reason) => {
+                        rejected.push(Rejected::new(reason, Value::from(data).to_string()))
+                    }
+                }
+            }
+        }
+    }
+    (rows, rejected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+    use serde_json::json;
+
+    fn string_value(text: &str) -> Option<AnyValue> {
+        Some(AnyValue {
+            value: Some(any_value::Value::StringValue(text.to_owned())),
+        })
+    }
+
+    #[test]
+    fn test_parse_protobuf() {
+        let request = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: Some(Resource {
+                    attributes: vec![KeyValue {
+                        key: "service.name".to_owned(),
+                        value: string_value("checkout"),
+                    }],
+                }),
+                scope_logs: vec![ScopeLogs {
+                    scope: None,
+                    log_records: vec![LogRecord {
+                        time_unix_nano: 1_711_302_824_000_000_000,
+                        severity_number: 17,
+                        body: string_value("payment failed"),
+                        attributes: vec![
+                            KeyValue {
+                                key: "order".to_owned(),
+                                value: Some(AnyValue {
+                                    value: Some(any_value::Value::IntValue(42)),
+                                }),
+                            },
+                            KeyValue {
+                                key: "level".to_owned(),
+                                value: string_value("debug"),
+                            },
+                            KeyValue {
+                                key: "digest".to_owned(),
+                                value: Some(AnyValue {
+                                    value: Some(any_value::Value::BytesValue(b"hi!".to_vec())),
+                                }),
+                            },
+                        ],
+                        trace_id: vec![0x5b, 0x8e, 0xff, 0xf7],
+                        span_id: vec![0xee, 0xe1],
+                        ..Default::default()
+                    }],
+                }],
+            }],
+        };
+        let decoded = ExportLogsServiceRequest::decode(request.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded, request);
+        let (rows, rejected) = parse_request(&decoded);
+        assert!(rejected.is_empty());
+        assert_eq!(rows[0].level, "ERROR");
+        assert_eq!(rows[0].source.as_deref(), Some("checkout"));
+        assert_eq!(
+            rows[0].time,
+            Utc.with_ymd_and_hms(2024, 3, 24, 17, 53, 44).unwrap()
+        );
+        assert_eq!(
+            rows[0].data,
+            json!({
+                "message": "payment failed",
+                "attributes": {"order": 42, "level": "debug", "digest": "aGkh"},
+                "source": "checkout",
+                "time": "2024-03-24T17:53:44Z",
+                "trace_id": "5b8efff7",
+                "span_id": "eee1",
+                "resource": {"service.name": "checkout"},
+            })
+        );
+        assert!(rows[0].words.contains(&"5b8efff7".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let body = json!({"resourceLogs": [{
+            "resource": {"attributes": [{"key": "service.name", "value": {"stringValue": "api"}}]},
+            "scopeLogs": [{
+                "scope": {"name": "my.library"},
+                "logRecords": [{
+                    "timeUnixNano": "1711302824000000000",
+                    "severityText": "warn",
+                    "body": {"kvlistValue": {"values": [
+                        {"key": "user", "value": {"stringValue": "ann"}},
+                        {"key": "retries", "value": {"intValue": "3"}},
+                        {"key": "tags", "value": {"arrayValue": {"values": [{"boolValue": true}]}}}
+                    ]}},
+                    "attributes": [
+                        {"key": "time", "value": {"stringValue": "2001-01-01T00:00:00Z"}},
+                        {"key": "digest", "value": {"bytesValue": "aGkh"}}
+                    ],
+                    "traceId": "5B8EFFF798038103D269B633813FC60C",
+                    "spanId": "eee19b7ec3c1b174"
+                }]
+            }]
+        }]});
+        let request = parse_json(body.to_string().as_bytes()).unwrap();
+        let (rows, rejected) = parse_request(&request);
+        assert!(rejected.is_empty());
+        assert_eq!(rows[0].level, "WARNING");
+        assert_eq!(rows[0].source.as_deref(), Some("api"));
+        assert_eq!(
+            rows[0].data["message"],
+            json!({"user": "ann", "retries": 3, "tags": [true]})
+        );
+        assert_eq!(rows[0].data["trace_id"], "5b8efff798038103d269b633813fc60c");
+        assert_eq!(rows[0].data["span_id"], "eee19b7ec3c1b174");
+        assert_eq!(rows[0].data["scope"], "my.library");
+        assert_eq!(
+            rows[0].data["attributes"],
+            json!({"time": "2001-01-01T00:00:00Z", "digest": "aGkh"})
+        );
+
+        // Without timestamps, the time is the ingest time and not an attribute
+        let mut body = body;
+        let record = &mut body["resourceLogs"][0]["scopeLogs"][0]["logRecords"][0];
+        record.as_object_mut().unwrap().remove("timeUnixNano");
+        let request = parse_json(body.to_string().as_bytes()).unwrap();
+        let (rows, _) = parse_request(&request);
+        assert_eq!(rows[0].time, rows[0].ingest_time);
+
+        assert!(parse_json(b"[]").is_err());
+        assert!(parse_json(b"{\"resourceLogs\": ").is_err());
+    }
+}
@@ -1,8 +1,371 @@
-use std::cmp::max;
+use std::{cmp::max, collections::HashSet, sync::OnceLock};
 
 use bigdecimal::ToPrimitive;
 use chrono::{NaiveDateTime, Utc};
-use sqlx::{types::BigDecimal, PgPool, Row};
+use regex::Regex;
+use sqlx::{postgres::PgRow, types::BigDecimal, PgConnection, PgPool, Row};
+
+use crate::errors::AppError;
+use crate::filter::{self, BoundParams, Expr, InlineParams, WordMatch};
+use crate::ingest::{LogRow, Rejected};
+use crate::model::{
+    ColumnDef, ColumnPatch, Cursor, FilterDef, LogPage, PageQuery, SortOrder, ViewPatch, ViewQuery,
+    ViewVersion,
+};
+
+fn bucket_interval(start: NaiveDateTime, end: NaiveDateTime) -> String {
+    let interval_millis = (end - start).num_milliseconds();
+    match (end - start).num_microseconds() {
+        Some(val) => format!("{} microseconds", max(val / 119, 10)),
+        None => format!("{} milliseconds", max(interval_millis / 119, 10)),
+    }
+}
+
+/// Start times of the `count` buckets `time_bucket_gapfill` returns for
+/// `bucket_interval(start, end)`. TimescaleDB aligns them on 2000-01-03.
+pub fn bucket_starts(start: NaiveDateTime, end: NaiveDateTime, count: usize) -> Vec<NaiveDateTime> {
+    let width = match (end - start).num_microseconds() {
+        Some(val) => max(val / 119, 10),
+        None => max((end - start).num_milliseconds() / 119, 10) * 1000,
+    };
+    let origin = chrono::NaiveDate::from_ymd_opt(2000, 1, 3)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    let since_origin = (start - origin).num_microseconds().unwrap_or(0);
+    let first = origin + chrono::Duration::microseconds(since_origin.div_euclid(width) * width);
+    (0..count as i64)
+        .map(|i| first + chrono::Duration::microseconds(i * width))
+        .collect()
+}
+
+type LogLine = (NaiveDateTime, String, Vec<serde_json::Value>);
+
+/// Aggregates a column can be charted with. An empty `metric_agg` leaves the column out of metrics.
+pub const METRIC_AGGS: [&str; 5] = ["avg", "count", "max", "min", "sum"];
+
+/// View names are part of their aggregate names, so they must be plain SQL
+/// identifiers short enough for the `_sec_count` suffix.
+fn check_view_name(name: &str) -> Result<(), AppError> {
+    static NAME: OnceLock<Regex> = OnceLock::new();
+    let pattern = NAME.get_or_init(|| Regex::new(r"^[a-z_][a-z0-9_]{0,52}$").unwrap());
+    if pattern.is_match(name) {
+        Ok(())
+    } else {
+        Err(AppError::BadRequest(format!(
+            "invalid view name {:?}: use up to 53 lowercase letters, digits and underscores, not starting with a digit",
+            name
+        )))
+    }
+}
+
+/// Column names are shown as is and used in `view/column` Grafana targets.
+fn check_column(name: &str, metric_agg: &str) -> Result<(), AppError> {
+    if name.trim().is_empty()
+        || name.chars().count() > 63
+        || name.contains('/')
+        || name.chars().any(char::is_control)
+    {
+        return Err(AppError::BadRequest(format!(
+            "invalid column name {:?}: use 1 to 63 characters, without / or control characters",
+            name
+        )));
+    }
+    if !metric_agg.is_empty() && !METRIC_AGGS.contains(&metric_agg) {
+        return Err(AppError::BadRequest(format!(
+            "invalid metric_agg {:?} for column {}: use one of {}, or an empty string",
+            metric_agg,
+            name,
+            METRIC_AGGS.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// A view lists at least one column, each of them once.
+fn check_column_list(column_names: &[String]) -> Result<(), AppError> {
+    if column_names.is_empty() {
+        return Err(AppError::BadRequest(
+            "a view needs at least one column".to_owned(),
+        ));
+    }
+    let mut seen = HashSet::new();
+    for name in column_names {
+        if !seen.insert(name) {
+            return Err(AppError::BadRequest(format!(
+                "column {} is listed twice",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Store a view and its columns, replacing the columns of the same names.
+async fn save_view(
+    conn: &mut PgConnection,
+    filter_name: &str,
+    filter_query: &str,
+    columns: &[ColumnDef],
+) -> Result<(), AppError> {
+    let names: Vec<&str> = columns.iter().map(|c| c.name.as_str()).collect();
+    let queries: Vec<&str> = columns.iter().map(|c| c.query.as_str()).collect();
+    let metric_aggs: Vec<&str> = columns.iter().map(|c| c.metric_agg.as_str()).collect();
+    sqlx::query(
+        "INSERT INTO cols (name, query, metric_agg)
+            SELECT * FROM UNNEST($1::text[], $2::text[], $3::text[])
+            ON CONFLICT (name) DO UPDATE SET query = EXCLUDED.query, metric_agg = EXCLUDED.metric_agg",
+    )
+    .bind(&names)
+    .bind(&queries)
+    .bind(&metric_aggs)
+    .execute(&mut *conn)
+    .await?;
+    let column_names: Vec<String> = names.into_iter().map(str::to_owned).collect();
+    link_columns(&mut *conn, filter_name, &column_names).await?;
+    sqlx::query(
+        "INSERT INTO filters (name, query) VALUES ($1, $2)
+            ON CONFLICT (name) DO UPDATE SET query = EXCLUDED.query",
+    )
+    .bind(filter_name)
+    .bind(filter_query)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Views using any of the columns.
+async fn column_users(
+    conn: &mut PgConnection,
+    column_names: &[String],
+) -> Result<Vec<String>, AppError> {
+    Ok(sqlx::query_scalar(
+        "SELECT DISTINCT filter_name FROM column_filter WHERE column_name = ANY($1)",
+    )
+    .bind(column_names)
+    .fetch_all(&mut *conn)
+    .await?)
+}
+
+/// Store the current definition of each view as a new version, unless it is
+/// the latest version already. Called before a change with no author, this
+/// keeps the definitions changed outside of the API or predating the history.
+async fn record_versions(
+    conn: &mut PgConnection,
+    filter_names: &[String],
+    author: Option<&str>,
+) -> Result<(), AppError> {
+    sqlx::query(
+        "WITH snapshot AS (
+            SELECT filters.name, filters.query, COALESCE((
+                SELECT jsonb_agg(jsonb_build_object(
+                        'name', cols.name,
+                        'query', COALESCE(cols.query, ''),
+                        'metric_agg', COALESCE(cols.metric_agg, '')
+                    ) ORDER BY column_filter.idx)
+                    FROM column_filter JOIN cols ON cols.name = column_filter.column_name
+                    WHERE column_filter.filter_name = filters.name
+            ), '[]') AS columns
+            FROM filters WHERE filters.name = ANY($1)
+        )
+        INSERT INTO view_versions (filter_name, version, author, query, columns)
+            SELECT snapshot.name, COALESCE(latest.version, 0) + 1, $2, snapshot.query, snapshot.columns
+                FROM snapshot LEFT JOIN LATERAL (
+                    SELECT version, query, columns FROM view_versions
+                        WHERE filter_name = snapshot.name ORDER BY version DESC LIMIT 1
+                ) latest ON true
+                WHERE latest.version IS NULL
+                   OR latest.query IS DISTINCT FROM snapshot.query
+                   OR latest.columns IS DISTINCT FROM snapshot.columns",
+    )
+    .bind(filter_names)
+    .bind(author)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Decode a `version, time, author, query, columns` row of `view_versions`.
+fn view_version(row: &PgRow) -> Result<ViewVersion, AppError> {
+    let columns = row.try_get::<serde_json::Value, _>(4)?;
+    Ok(ViewVersion {
+        version: row.try_get(0)?,
+        time: row.try_get(1)?,
+        author: row.try_get(2)?,
+        query: row.try_get(3)?,
+        columns: serde_json::from_value(columns)
+            .map_err(|err| AppError::Internal(format!("invalid view version columns: {}", err)))?,
+    })
+}
+
+/// Replace the columns of a view, which keep the given order.
+async fn link_columns(
+    conn: &mut PgConnection,
+    filter_name: &str,
+    column_names: &[String],
+) -> Result<(), AppError> {
+    sqlx::query("DELETE FROM column_filter WHERE filter_name = $1")
+        .bind(filter_name)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query(
+        "INSERT INTO column_filter (column_name, filter_name, idx)
+            SELECT name, $2, (idx - 1)::int FROM UNNEST($1::text[]) WITH ORDINALITY AS c(name, idx)",
+    )
+    .bind(column_names)
+    .bind(filter_name)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Create the `_sec_count` and `_min_count` continuous aggregates of a view,
+/// replacing existing ones, and check TimescaleDB lists both of them.
+/// They are created empty, as a transaction cannot fill them, and their
+/// policies refresh them from the start of the logs.
+async fn create_mat_views(
+    conn: &mut PgConnection,
+    filter_name: &str,
+    filter: &Expr,
+) -> Result<(), AppError> {
+    // DDL cannot take bind parameters, so values are inlined as escaped literals.
+    let filter_query = filter.to_sql(&mut InlineParams);
+    drop_mat_views(&mut *conn, filter_name).await?;
+    let aggregates = [
+        (format!("{filter_name}_sec_count"), "1s", "10 seconds"),
+        (format!("{filter_name}_min_count"), "1 minute", "10 minute"),
+    ];
+    for (view, bucket, schedule) in &aggregates {
+        let statements = [
+            format!(
+                "CREATE MATERIALIZED VIEW {view} (time_bucket, count) WITH (timescaledb.continuous)
+                    AS SELECT time_bucket('{bucket}', time), COUNT(*) from logs where {filter_query} GROUP BY time_bucket('{bucket}', time)
+                    WITH NO DATA"
+            ),
+            format!(
+                "SELECT add_continuous_aggregate_policy('{view}',
+                    start_offset => null,
+                    end_offset => null,
+                    schedule_interval => INTERVAL '{schedule}')"
+            ),
+        ];
+        for statement in statements {
+            sqlx::query(statement.as_str()).execute(&mut *conn).await?;
+        }
+    }
+    let created: i64 = sqlx::query(
+        "SELECT COUNT(*) FROM timescaledb_information.continuous_aggregates WHERE view_name IN ($1, $2)",
+    )
+    .bind(&aggregates[0].0)
+    .bind(&aggregates[1].0)
+    .fetch_one(&mut *conn)
+    .await?
+    .try_get(0)?;
+    if created != 2 {
+        return Err(AppError::Internal(format!(
+            "aggregates of view {} were not created",
+            filter_name
+        )));
+    }
+    Ok(())
+}
+
+/// The aggregates of a view that exist.
+async fn existing_mat_views(
+    conn: &mut PgConnection,
+    filter_name: &str,
+) -> Result<Vec<String>, AppError> {
+    Ok(sqlx::query_scalar(
+        "SELECT relname::text FROM pg_class WHERE relname = ANY($1) AND pg_table_is_visible(oid)",
+    )
+    .bind([
+        format!("{filter_name}_sec_count"),
+        format!("{filter_name}_min_count"),
+    ])
+    .fetch_all(&mut *conn)
+    .await?)
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Drop the aggregates of a view and their refresh policies, skipping the ones
+/// that were never created.
+async fn drop_mat_views(conn: &mut PgConnection, filter_name: &str) -> Result<(), AppError> {
+    for view in existing_mat_views(&mut *conn, filter_name).await? {
+        sqlx::query(
+            "SELECT remove_continuous_aggregate_policy($1::text::regclass, if_exists => true)",
+        )
+        .bind(&view)
+        .execute(&mut *conn)
+        .await?;
+        let query = format!("DROP MATERIALIZED VIEW {}", quote_ident(&view));
+        sqlx::query(query.as_str()).execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+/// Rename the aggregates of a view, which keep their data and refresh policies.
+async fn rename_mat_views(conn: &mut PgConnection, old: &str, new: &str) -> Result<(), AppError> {
+    for view in existing_mat_views(&mut *conn, old).await? {
+        let renamed = format!("{}{}", new, &view[old.len()..]);
+        let query = format!(
+            "ALTER MATERIALIZED VIEW {} RENAME TO {}",
+            quote_ident(&view),
+            quote_ident(&renamed)
+        );
+        sqlx::query(query.as_str()).execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+/// Decode a `name, query, metric_agg` row of `cols`.
+fn column_def(row: &PgRow) -> Result<ColumnDef, AppError> {
+    Ok(ColumnDef {
+        name: row.try_get(0)?,
+        query: row.try_get::<Option<String>, _>(1)?.unwrap_or_default(),
+        metric_agg: row.try_get::<Option<String>, _>(2)?.unwrap_or_default(),
+    })
+}
+
+/// Decode `time, level` followed by `col_number` view columns, starting at `first`.
+/// A NULL level reads as an empty string, and a NULL column as `null`.
+fn log_line(row: &PgRow, first: usize, col_number: usize) -> Result<LogLine, sqlx::Error> {
+    let mut ret_line: Vec<serde_json::Value> = Vec::new();
+    for i in first + 2..first + 2 + col_number {
+        ret_line.push(
+            row.try_get::<Option<serde_json::Value>, _>(i)?
+                .unwrap_or(serde_json::Value::Null),
+        )
+    }
+    Ok((
+        row.try_get::<NaiveDateTime, _>(first)?,
+        row.try_get::<Option<String>, _>(first + 1)?
+            .unwrap_or_default(),
+        ret_line,
+    ))
+}
+
+/// Quote a CSV field for COPY, where unquoted empty fields are NULL.
+fn csv_field(out: &mut String, value: &str) {
+    out.push('"');
+    out.push_str(&value.replace('"', "\"\""));
+    out.push('"');
+}
+
+/// A Postgres array literal of texts, such as `{"a","b"}`.
+fn array_literal(values: &[String]) -> String {
+    let elements: Vec<String> = values
+        .iter()
+        .map(|value| format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+    format!("{{{}}}", elements.join(","))
+}
+
+fn csv_time(time: &chrono::DateTime<Utc>) -> String {
+    time.naive_utc().format("%Y-%m-%d %H:%M:%S%.6f").to_string()
+}
 
 #[derive(Clone)]
 pub struct Repository {
@@ -41,28 +404,83 @@ impl Repository {
             .await?;
         Ok(rows
             .into_iter()
-            .map(|r| r.get::<String, _>(0).into())
+            .map(|r| r.get::<String, _>(0))
             .collect::<Vec<String>>())
     }
 
+    /// Views and columns whose query does not parse, such as the raw SQL
+    /// stored before the filter language, as (kind, name, query, error).
+    pub async fn invalid_queries(
+        &self,
+    ) -> Result<Vec<(&'static str, String, String, String)>, sqlx::error::Error> {
+        let rows = sqlx::query(
+            "SELECT 'view', name, COALESCE(query, '') FROM filters
+            UNION ALL SELECT 'column', name, COALESCE(query, '') FROM cols
+            ORDER BY 1 DESC, 2",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                let name = row.get::<String, _>(1);
+                let query = row.get::<String, _>(2);
+                let (kind, parsed) = if row.get::<String, _>(0) == "view" {
+                    ("view", filter::parse_filter(&query).map(drop))
+                } else {
+                    ("column", filter::parse_column(&query).map(drop))
+                };
+                parsed.err().map(|err| (kind, name, query, err.to_string()))
+            })
+            .collect())
+    }
+
     pub async fn get_metric_query_agg(
         &self,
         metric_name: String,
-    ) -> Result<(String, String), sqlx::error::Error> {
+    ) -> Result<(String, String), AppError> {
         let row = sqlx::query("SELECT query, metric_agg FROM cols WHERE name = $1")
-            .bind(metric_name)
-            .fetch_one(&self.pool)
-            .await?;
-        let col_query: String = row.try_get::<String, _>(0)?.into();
-        let metric_agg: String = row.try_get::<String, _>(1)?.into();
+            .bind(&metric_name)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("column {} not found", metric_name)))?;
+        let col_query: String = row.try_get::<String, _>(0)?;
+        let metric_agg: String = row.try_get::<String, _>(1)?;
         Ok((col_query, metric_agg))
     }
 
-    pub async fn get_filter(&self, view_name: String) -> Result<String, sqlx::error::Error> {
+    /// The filter of a view, with the query and metric_agg of one of its
+    /// columns. Columns that exist but are not in the view are not found.
+    pub async fn get_view_column(
+        &self,
+        view_name: &str,
+        column_name: &str,
+    ) -> Result<(String, String, String), AppError> {
+        let filter_query = self.get_filter(view_name.to_owned()).await?;
+        let row = sqlx::query(
+            "SELECT cols.query, cols.metric_agg
+                FROM column_filter JOIN cols ON cols.name = column_filter.column_name
+                WHERE column_filter.filter_name = $1 AND column_filter.column_name = $2",
+        )
+        .bind(view_name)
+        .bind(column_name)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| {
+            AppError::NotFound(format!(
+                "column {} not found in view {}",
+                column_name, view_name
+            ))
+        })?;
+        Ok((filter_query, row.try_get(0)?, row.try_get(1)?))
+    }
+
+    pub async fn get_filter(&self, view_name: String) -> Result<String, AppError> {
         let try_filter = sqlx::query("SELECT query FROM filters WHERE name = $1")
-            .bind(view_name)
-            .fetch_one(&self.pool)
-            .await?;
+            .bind(&view_name)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("view {} not found", view_name)))?;
         Ok(try_filter.try_get::<String, _>(0)?)
     }
 
@@ -73,26 +491,31 @@ impl Repository {
         metric_agg: String,
         col_query: String,
         where_query: String,
-    ) -> Result<Vec<Option<f64>>, sqlx::error::Error> {
-        let interval_millis = (end - start).num_milliseconds();
-        let interval_micro = (end - start).num_microseconds();
-        let interval_str = match interval_micro {
-            Some(val) => format!("{} microseconds", max(val / 119, 10)),
-            None => format!("{} milliseconds", max(interval_millis / 119, 10)),
-        };
+    ) -> Result<Vec<Option<f64>>, AppError> {
+        if !METRIC_AGGS.contains(&metric_agg.as_str()) {
+            return Err(AppError::BadRequest(format!(
+                "column has no metric_agg to chart with, such as {}",
+                METRIC_AGGS.join(", ")
+            )));
+        }
+        let interval_str = bucket_interval(start.naive_utc(), end.naive_utc());
+        let mut params = BoundParams::new(0);
+        let col_sql = filter::parse_column(&col_query)?.to_sql(&mut params);
+        let where_sql = filter::parse_filter(&where_query)?.to_sql(&mut params);
         let query = format!(
             "
-        SELECT {metric_agg}(({col_query})::numeric)  
+        SELECT {metric_agg}(({col_sql})::numeric)  
             FROM logs 
-            WHERE jsonb_typeof({col_query}) = 'number' 
-              AND {where_query}
+            WHERE jsonb_typeof({col_sql}) = 'number' 
+              AND {where_sql}
               AND time >= '{}'::TIMESTAMP 
               AND time <= '{}'::TIMESTAMP 
             GROUP BY time_bucket_gapfill('{interval_str}', time) 
             LIMIT 120",
             start, end,
         );
-        Ok(sqlx::query(query.as_str())
+        Ok(params
+            .bind(sqlx::query(query.as_str()))
             .fetch_all(&self.pool)
             .await?
             .into_iter()
@@ -103,100 +526,405 @@ impl Repository {
             .collect::<Vec<Option<f64>>>())
     }
 
-    pub async fn create_mat_views(
-        &self,
-        filter_name: String,
-        filter_query: String,
-    ) -> Result<(), sqlx::error::Error> {
-        let query = format!(
-            "DROP MATERIALIZED VIEW IF EXISTS {filter_name}_sec_count; DROP MATERIALIZED VIEW IF EXISTS {filter_name}_min_count; 
-            CREATE MATERIALIZED VIEW {filter_name}_sec_count (time_bucket, count) WITH (timescaledb.continuous)
-                AS SELECT time_bucket('1s', time), COUNT(*) from logs where {filter_query} GROUP BY time_bucket('1s', time);
-            CREATE MATERIALIZED VIEW {filter_name}_min_count (time_bucket, count) WITH (timescaledb.continuous)
-                AS SELECT time_bucket('1 minute', time), COUNT(*) from logs where {filter_query} GROUP BY time_bucket('1 minute', time);
-            SELECT add_continuous_aggregate_policy('{filter_name}_min_count',
-                start_offset => null,
-                end_offset => null,
-                schedule_interval => INTERVAL '10 minute');
-            SELECT add_continuous_aggregate_policy('{filter_name}_sec_count',
-                start_offset => null,
-                end_offset => null,
-                schedule_interval => INTERVAL '10 seconds');",
-        );
-        let _res = sqlx::raw_sql(query.as_str()).execute_many(&self.pool);
-        Ok(())
-    }
-
-    pub async fn delete_view(&self, view_name: String) -> Result<(), sqlx::error::Error> {
-        let query = format!(
-            "
-            DELETE FROM filter WHERE name = {view_name}; 
-            DELETE FROM column_filter WHERE filter_name = {view_name}; 
-            DELETE FROM cols WHERE name NOT IN (SELECT column_name FROM column_filters);"
-        );
-        let _res = sqlx::raw_sql(&query.as_str()).execute_many(&self.pool);
+    /// Delete a view, its aggregates and those of its columns no other view uses, in one transaction.
+    pub async fn delete_view(&self, view_name: &str) -> Result<(), AppError> {
+        if view_name == "logs" {
+            return Err(AppError::BadRequest(
+                "the logs view cannot be deleted".to_owned(),
+            ));
+        }
+        let mut transaction = self.pool.begin().await?;
+        // Keep the definition for a rollback, if the history does not have it yet.
+        record_versions(&mut transaction, &[view_name.to_owned()], None).await?;
+        let deleted = sqlx::query("DELETE FROM filters WHERE name = $1")
+            .bind(view_name)
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected();
+        if deleted == 0 {
+            return Err(AppError::NotFound(format!("view {} not found", view_name)));
+        }
+        let column_names: Vec<String> = sqlx::query_scalar(
+            "DELETE FROM column_filter WHERE filter_name = $1 RETURNING column_name",
+        )
+        .bind(view_name)
+        .fetch_all(&mut *transaction)
+        .await?;
+        sqlx::query(
+            "DELETE FROM cols WHERE name = ANY($1)
+                AND NOT EXISTS (SELECT 1 FROM column_filter WHERE column_name = cols.name)",
+        )
+        .bind(&column_names)
+        .execute(&mut *transaction)
+        .await?;
+        drop_mat_views(&mut transaction, view_name).await?;
+        transaction.commit().await?;
         Ok(())
     }
 
+    /// Create or replace a view, its columns and its aggregates, in one transaction.
     pub async fn upsert_columns_and_filters(
         &self,
-        column_names: &Vec<String>,
-        columns_queries: &Vec<(String, String)>,
-        filter_name: &String,
-        filter_query: &String,
-    ) -> Result<(), sqlx::error::Error> {
-        let values: Vec<String> = column_names
-            .into_iter()
+        column_names: &[String],
+        columns_queries: &[(String, String)],
+        filter_name: &str,
+        filter_query: &str,
+        filter: &Expr,
+        author: Option<&str>,
+    ) -> Result<(), AppError> {
+        check_view_name(filter_name)?;
+        check_column_list(column_names)?;
+        for (name, (_, metric_agg)) in column_names.iter().zip(columns_queries) {
+            check_column(name, metric_agg)?;
+        }
+        let columns: Vec<ColumnDef> = column_names
+            .iter()
             .zip(columns_queries)
-            .map(|(name, (query, metric_agg))| {
-                [
-                    "('",
-                    name,
-                    "','",
-                    &query.replace("'", "''"),
-                    "','",
-                    &metric_agg,
-                    "')",
-                ]
-                .join("")
+            .map(|(name, (query, metric_agg))| ColumnDef {
+                name: name.clone(),
+                query: query.clone(),
+                metric_agg: metric_agg.clone(),
             })
             .collect();
-        let query =format!(
-                    "INSERT INTO cols (name, query, metric_agg) VALUES {} ON CONFLICT (name) DO UPDATE SET query = EXCLUDED.query",
-                    values.join(","));
-        let _res = sqlx::query(query.as_str()).execute(&self.pool).await;
-
-        let _res = sqlx::query("DELETE FROM column_filter WHERE filter_name = $1")
-            .bind(filter_name)
-            .execute(&self.pool)
-            .await;
-        let filter_column_values: Vec<String> = column_names
-            .into_iter()
-            .enumerate()
-            .map(|(idx, name)| format!("('{}', '{}', {})", name, filter_name, idx))
-            .collect();
 
-        let query = format!(
-            "INSERT INTO column_filter (column_name, filter_name, idx) VALUES {}",
-            filter_column_values.join(",")
-        );
-        let _res = sqlx::query(&query.as_str()).execute(&self.pool).await;
+        let mut transaction = self.pool.begin().await?;
+        // Views sharing the columns change as well.
+        let mut changed = column_users(&mut transaction, column_names).await?;
+        changed.push(filter_name.to_owned());
+        record_versions(&mut transaction, &changed, None).await?;
+        save_view(&mut transaction, filter_name, filter_query, &columns).await?;
+        create_mat_views(&mut transaction, filter_name, filter).await?;
+        record_versions(&mut transaction, &changed, author).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
 
-        let _res = sqlx::query(
-                format!(
-                    "INSERT INTO filters (name, query) VALUES ('{}', '{}') ON CONFLICT (name) DO UPDATE SET query = EXCLUDED.query",
-                    filter_name, filter_query.replace("'", "''")
-                ).as_str()).execute(&self.pool).await;
+    /// Definition of a view, with its columns in order.
+    pub async fn get_view(&self, view_name: &str) -> Result<ViewQuery, AppError> {
+        let rows = sqlx::query(
+            "SELECT filters.query, cols.name, cols.query, cols.metric_agg
+                FROM filters
+                    LEFT JOIN column_filter ON column_filter.filter_name = filters.name
+                    LEFT JOIN cols ON cols.name = column_filter.column_name
+                WHERE filters.name = $1
+                ORDER BY column_filter.idx",
+        )
+        .bind(view_name)
+        .fetch_all(&self.pool)
+        .await?;
+        let Some(first) = rows.first() else {
+            return Err(AppError::NotFound(format!("view {} not found", view_name)));
+        };
+        let query = first.try_get::<String, _>(0)?;
+        let mut columns = Vec::new();
+        for row in &rows {
+            if let Some(name) = row.try_get::<Option<String>, _>(1)? {
+                columns.push(ColumnDef {
+                    name,
+                    query: row.try_get::<Option<String>, _>(2)?.unwrap_or_default(),
+                    metric_agg: row.try_get::<Option<String>, _>(3)?.unwrap_or_default(),
+                });
+            }
+        }
+        Ok(ViewQuery {
+            columns,
+            filter: FilterDef {
+                name: view_name.to_owned(),
+                query,
+            },
+        })
+    }
+
+    /// Rename a view or change its filter or columns, in one transaction.
+    /// A new filter rebuilds the aggregates, a new name renames them.
+    /// Returns the name of the view.
+    pub async fn update_view(
+        &self,
+        view_name: &str,
+        patch: &ViewPatch,
+        author: Option<&str>,
+    ) -> Result<String, AppError> {
+        let mut transaction = self.pool.begin().await?;
+        let old_query: String =
+            sqlx::query_scalar("SELECT query FROM filters WHERE name = $1 FOR UPDATE")
+                .bind(view_name)
+                .fetch_optional(&mut *transaction)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("view {} not found", view_name)))?;
+        record_versions(&mut transaction, &[view_name.to_owned()], None).await?;
+        let name = patch.name.as_deref().unwrap_or(view_name);
+        if name != view_name {
+            if view_name == "logs" {
+                return Err(AppError::BadRequest(
+                    "the logs view cannot be renamed".to_owned(),
+                ));
+            }
+            check_view_name(name)?;
+            let taken = sqlx::query("SELECT 1 FROM filters WHERE name = $1")
+                .bind(name)
+                .fetch_optional(&mut *transaction)
+                .await?;
+            if taken.is_some() {
+                return Err(AppError::Conflict(format!("view {} already exists", name)));
+            }
+            sqlx::query("UPDATE filters SET name = $2 WHERE name = $1")
+                .bind(view_name)
+                .bind(name)
+                .execute(&mut *transaction)
+                .await?;
+            sqlx::query("UPDATE column_filter SET filter_name = $2 WHERE filter_name = $1")
+                .bind(view_name)
+                .bind(name)
+                .execute(&mut *transaction)
+                .await?;
+            // The history follows the view, after that of a deleted view of the same name.
+            sqlx::query(
+                "UPDATE view_versions SET filter_name = $2, version = version + (
+                    SELECT COALESCE(max(version), 0) FROM view_versions WHERE filter_name = $2
+                ) WHERE filter_name = $1",
+            )
+            .bind(view_name)
+            .bind(name)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        if let Some(column_names) = &patch.columns {
+            check_column_list(column_names)?;
+            let missing: Option<String> = sqlx::query_scalar(
+                "SELECT name FROM UNNEST($1::text[]) AS c(name)
+                    WHERE NOT EXISTS (SELECT 1 FROM cols WHERE cols.name = c.name)",
+            )
+            .bind(column_names)
+            .fetch_optional(&mut *transaction)
+            .await?;
+            if let Some(missing) = missing {
+                return Err(AppError::BadRequest(format!(
+                    "column {} not found",
+                    missing
+                )));
+            }
+            link_columns(&mut transaction, name, column_names).await?;
+        }
+        match &patch.query {
+            Some(query) if *query != old_query => {
+                let filter = filter::parse_filter(query)?;
+                sqlx::query("UPDATE filters SET query = $2 WHERE name = $1")
+                    .bind(name)
+                    .bind(query)
+                    .execute(&mut *transaction)
+                    .await?;
+                drop_mat_views(&mut transaction, view_name).await?;
+                create_mat_views(&mut transaction, name, &filter).await?;
+            }
+            _ if name != view_name => rename_mat_views(&mut transaction, view_name, name).await?,
+            _ => {}
+        }
+        record_versions(&mut transaction, &[name.to_owned()], author).await?;
+        transaction.commit().await?;
+        Ok(name.to_owned())
+    }
+
+    /// Versions of a view, latest first. The history of a deleted view is kept.
+    pub async fn list_versions(&self, view_name: &str) -> Result<Vec<ViewVersion>, AppError> {
+        let versions = sqlx::query(
+            "SELECT version, time, author, query, columns FROM view_versions
+                WHERE filter_name = $1 ORDER BY version DESC",
+        )
+        .bind(view_name)
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(view_version)
+        .collect::<Result<Vec<ViewVersion>, AppError>>()?;
+        if versions.is_empty() {
+            self.get_filter(view_name.to_owned()).await?;
+        }
+        Ok(versions)
+    }
+
+    pub async fn get_version(
+        &self,
+        view_name: &str,
+        version: i32,
+    ) -> Result<ViewVersion, AppError> {
+        let row = sqlx::query(
+            "SELECT version, time, author, query, columns FROM view_versions
+                WHERE filter_name = $1 AND version = $2",
+        )
+        .bind(view_name)
+        .bind(version)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| {
+            AppError::NotFound(format!(
+                "version {} of view {} not found",
+                version, view_name
+            ))
+        })?;
+        view_version(&row)
+    }
+
+    /// Restore a version of a view, as a new version, re-creating the view if it was
+    /// deleted. The aggregates are rebuilt when the filter changes.
+    pub async fn rollback_view(
+        &self,
+        view_name: &str,
+        version: i32,
+        author: Option<&str>,
+    ) -> Result<(), AppError> {
+        let target = self.get_version(view_name, version).await?;
+        let filter = filter::parse_filter(&target.query)?;
+        let column_names: Vec<String> = target.columns.iter().map(|c| c.name.clone()).collect();
+        check_view_name(view_name)?;
+        check_column_list(&column_names)?;
+
+        let mut transaction = self.pool.begin().await?;
+        let current: Option<String> =
+            sqlx::query_scalar("SELECT query FROM filters WHERE name = $1 FOR UPDATE")
+                .bind(view_name)
+                .fetch_optional(&mut *transaction)
+                .await?;
+        // Views sharing the restored columns change as well.
+        let mut changed = column_users(&mut transaction, &column_names).await?;
+        changed.push(view_name.to_owned());
+        record_versions(&mut transaction, &changed, None).await?;
+        save_view(&mut transaction, view_name, &target.query, &target.columns).await?;
+        if current.as_deref() != Some(target.query.as_str()) {
+            create_mat_views(&mut transaction, view_name, &filter).await?;
+        }
+        record_versions(&mut transaction, &changed, author).await?;
+        transaction.commit().await?;
         Ok(())
     }
 
-    pub async fn get_logs(
+    pub async fn list_columns(&self) -> Result<Vec<ColumnDef>, AppError> {
+        let rows = sqlx::query("SELECT name, query, metric_agg FROM cols ORDER BY name")
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter().map(column_def).collect()
+    }
+
+    pub async fn get_column(&self, column_name: &str) -> Result<ColumnDef, AppError> {
+        let row = sqlx::query("SELECT name, query, metric_agg FROM cols WHERE name = $1")
+            .bind(column_name)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("column {} not found", column_name)))?;
+        column_def(&row)
+    }
+
+    pub async fn create_column(&self, column: &ColumnDef) -> Result<(), AppError> {
+        check_column(&column.name, &column.metric_agg)?;
+        let created = sqlx::query(
+            "INSERT INTO cols (name, query, metric_agg) VALUES ($1, $2, $3) ON CONFLICT (name) DO NOTHING",
+        )
+        .bind(&column.name)
+        .bind(&column.query)
+        .bind(&column.metric_agg)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        if created == 0 {
+            return Err(AppError::Conflict(format!(
+                "column {} already exists",
+                column.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Rename a column or change its query or aggregate, for every view using it.
+    /// Returns the name of the column.
+    pub async fn update_column(
         &self,
-        start: chrono::NaiveDateTime,
-        end: chrono::NaiveDateTime,
-        offset: i64,
-        table: String,
-    ) -> Result<Vec<(NaiveDateTime, String, Vec<serde_json::Value>)>, sqlx::error::Error> {
+        column_name: &str,
+        patch: &ColumnPatch,
+        author: Option<&str>,
+    ) -> Result<String, AppError> {
+        let mut transaction = self.pool.begin().await?;
+        let row =
+            sqlx::query("SELECT name, query, metric_agg FROM cols WHERE name = $1 FOR UPDATE")
+                .bind(column_name)
+                .fetch_optional(&mut *transaction)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("column {} not found", column_name)))?;
+        let old = column_def(&row)?;
+        let users = column_users(&mut transaction, &[column_name.to_owned()]).await?;
+        record_versions(&mut transaction, &users, None).await?;
+        let column = ColumnDef {
+            name: patch.name.clone().unwrap_or(old.name),
+            query: patch.query.clone().unwrap_or(old.query),
+            metric_agg: patch.metric_agg.clone().unwrap_or(old.metric_agg),
+        };
+        check_column(&column.name, &column.metric_agg)?;
+        if column.name != column_name {
+            let taken = sqlx::query("SELECT 1 FROM cols WHERE name = $1")
+                .bind(&column.name)
+                .fetch_optional(&mut *transaction)
+                .await?;
+            if taken.is_some() {
+                return Err(AppError::Conflict(format!(
+                    "column {} already exists",
+                    column.name
+                )));
+            }
+        }
+        sqlx::query("UPDATE cols SET name = $2, query = $3, metric_agg = $4 WHERE name = $1")
+            .bind(column_name)
+            .bind(&column.name)
+            .bind(&column.query)
+            .bind(&column.metric_agg)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query("UPDATE column_filter SET column_name = $2 WHERE column_name = $1")
+            .bind(column_name)
+            .bind(&column.name)
+            .execute(&mut *transaction)
+            .await?;
+        record_versions(&mut transaction, &users, author).await?;
+        transaction.commit().await?;
+        Ok(column.name)
+    }
+
+    /// Delete a column that no view uses.
+    pub async fn delete_column(&self, column_name: &str) -> Result<(), AppError> {
+        let mut transaction = self.pool.begin().await?;
+        let views: Vec<String> = sqlx::query_scalar(
+            "SELECT filter_name FROM column_filter WHERE column_name = $1 ORDER BY filter_name",
+        )
+        .bind(column_name)
+        .fetch_all(&mut *transaction)
+        .await?;
+        if !views.is_empty() {
+            return Err(AppError::Conflict(format!(
+                "column {} is used by views {}",
+                column_name,
+                views.join(", ")
+            )));
+        }
+        let deleted = sqlx::query("DELETE FROM cols WHERE name = $1")
+            .bind(column_name)
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected();
+        if deleted == 0 {
+            return Err(AppError::NotFound(format!(
+                "column {} not found",
+                column_name
+            )));
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Render the columns and filter of a view, plus an optional word search.
+    /// Returns the number of columns, the select list and the where clause.
+    async fn view_sql(
+        &self,
+        table: &str,
+        search: Option<(WordMatch, &[String])>,
+        params: &mut BoundParams,
+    ) -> Result<(usize, String, String), AppError> {
         let query = "
         SELECT COUNT(*), filters.query, array_agg(cols.query ORDER BY idx)
             FROM column_filter
@@ -206,66 +934,230 @@ impl Repository {
             GROUP BY filters.name, filters.query";
         let row = sqlx::query(query)
             .bind(table)
-            .fetch_one(&self.pool)
-            .await
-            .unwrap();
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("view {} not found", table)))?;
 
         let col_number: usize = row.get::<i64, _>(0) as usize;
         let filter_query: String = row.get::<String, _>(1);
         let column_queries: Vec<String> = row.get::<Vec<String>, _>(2);
+        let column_sql = column_queries
+            .iter()
+            .map(|q| Ok(filter::parse_column(q)?.to_sql(params)))
+            .collect::<Result<Vec<String>, filter::ParseError>>()?;
+        let mut where_sql = filter::parse_filter(&filter_query)?.to_sql(params);
+        if let Some((mode, terms)) = search {
+            where_sql = format!("{} AND {}", where_sql, mode.to_sql(terms, params));
+        }
+        Ok((col_number, column_sql.join(","), where_sql))
+    }
+
+    pub async fn get_logs(
+        &self,
+        start: chrono::NaiveDateTime,
+        end: chrono::NaiveDateTime,
+        page: &PageQuery,
+        table: String,
+        search: Option<(WordMatch, &[String])>,
+    ) -> Result<LogPage, AppError> {
+        let cursor = page.cursor()?;
+        let page_size = page.page_size();
+        let mut params = BoundParams::new(0);
+        let (col_number, column_sql, mut where_sql) =
+            self.view_sql(&table, search, &mut params).await?;
+        let (direction, comparison) = match page.order {
+            SortOrder::Asc => ("ASC", ">"),
+            SortOrder::Desc => ("DESC", "<"),
+        };
+        if let Some(cursor) = cursor {
+            where_sql = format!(
+                "{} AND (time, id) {} ('{}'::TIMESTAMP, {})",
+                where_sql, comparison, cursor.time, cursor.id
+            );
+        }
+        // One extra row tells whether there is a next page.
         let query = format!(
-                    "SELECT time, level, {} from logs WHERE {} AND time >= '{}'::TIMESTAMP AND time <= '{}'::TIMESTAMP LIMIT 40 OFFSET {}",
-                    column_queries.join(","), filter_query, start, end, offset
+                    "SELECT id, time, level, {} from logs WHERE {} AND time >= '{}'::TIMESTAMP AND time <= '{}'::TIMESTAMP ORDER BY time {direction}, id {direction} LIMIT {}",
+                    column_sql, where_sql, start, end, page_size + 1
                 );
-        Ok(sqlx::query(query.as_str())
+        let mut rows = params
+            .bind(sqlx::query(query.as_str()))
+            .fetch_all(&self.pool)
+            .await?;
+        let next_cursor = if rows.len() as i64 > page_size {
+            rows.truncate(page_size as usize);
+            rows.last().map(|row| {
+                Cursor {
+                    id: row.get::<i64, _>(0),
+                    time: row.get::<NaiveDateTime, _>(1),
+                }
+                .encode()
+            })
+        } else {
+            None
+        };
+        let logs = rows
+            .iter()
+            .map(|row| log_line(row, 1, col_number))
+            .collect::<Result<_, _>>()?;
+        Ok(LogPage { logs, next_cursor })
+    }
+
+    /// The oldest running transaction, the next transaction to start, and the
+    /// last id handed out to `logs`. Ids are taken when rows are inserted, not
+    /// when they are committed, so a lower id can show up after a higher one.
+    /// Once every transaction up to `xmax` is over, all the ids handed out at
+    /// this point are committed or rolled back.
+    pub async fn tail_checkpoint(&self) -> Result<(i64, i64, i64), AppError> {
+        let row = sqlx::query(
+            "SELECT pg_snapshot_xmin(pg_current_snapshot())::text::bigint,
+                pg_snapshot_xmax(pg_current_snapshot())::text::bigint,
+                COALESCE(pg_sequence_last_value(pg_get_serial_sequence('logs', 'id')::regclass), 0)",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok((row.try_get(0)?, row.try_get(1)?, row.try_get(2)?))
+    }
+
+    pub async fn max_log_id(&self) -> Result<i64, sqlx::error::Error> {
+        sqlx::query("SELECT COALESCE(max(id), 0) FROM logs")
+            .fetch_one(&self.pool)
+            .await?
+            .try_get::<i64, _>(0)
+    }
+
+    /// COPY rows into `logs` and rejected records into `rejected_logs`, in a
+    /// single transaction. Returns the number of rows written to `logs`.
+    pub async fn insert_logs(
+        &self,
+        rows: &[LogRow],
+        rejected: &[Rejected],
+    ) -> Result<u64, sqlx::error::Error> {
+        let mut transaction = self.pool.begin().await?;
+        let mut written = 0;
+        if !rows.is_empty() {
+            let mut csv = String::new();
+            for row in rows {
+                csv_field(&mut csv, &csv_time(&row.time));
+                csv.push(',');
+                csv_field(&mut csv, &csv_time(&row.ingest_time));
+                csv.push(',');
+                csv_field(&mut csv, &row.data.to_string());
+                csv.push(',');
+                csv_field(&mut csv, &row.level);
+                csv.push(',');
+                if let Some(source) = &row.source {
+                    csv_field(&mut csv, source);
+                }
+                csv.push(',');
+                csv_field(&mut csv, &array_literal(&row.words));
+                csv.push('\n');
+            }
+            let mut copy = transaction
+                .copy_in_raw(
+                    "COPY logs (time, ingest_time, logdata, level, source, words) FROM STDIN WITH (FORMAT csv)",
+                )
+                .await?;
+            copy.send(csv.as_bytes()).await?;
+            written = copy.finish().await?;
+        }
+        if !rejected.is_empty() {
+            let now = csv_time(&Utc::now());
+            let mut csv = String::new();
+            for rejection in rejected {
+                csv_field(&mut csv, &now);
+                csv.push(',');
+                csv_field(&mut csv, &rejection.reason);
+                csv.push(',');
+                // Postgres text cannot hold NUL characters
+                csv_field(&mut csv, &rejection.payload.replace('\0', "\\u0000"));
+                csv.push('\n');
+            }
+            let mut copy = transaction
+                .copy_in_raw(
+                    "COPY rejected_logs (time, reason, payload) FROM STDIN WITH (FORMAT csv)",
+                )
+                .await?;
+            copy.send(csv.as_bytes()).await?;
+            copy.finish().await?;
+        }
+        transaction.commit().await?;
+        Ok(written)
+    }
+
+    /// Latest logs of a time range matching `filter`, as (time, level, source, logdata).
+    pub async fn get_annotations(
+        &self,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        filter: &Expr,
+        limit: i64,
+    ) -> Result<Vec<(NaiveDateTime, String, Option<String>, serde_json::Value)>, AppError> {
+        let mut params = BoundParams::new(0);
+        let where_sql = filter.to_sql(&mut params);
+        let query = format!(
+            "SELECT time, level, source, logdata FROM logs
+                WHERE {where_sql} AND time >= '{start}'::TIMESTAMP AND time <= '{end}'::TIMESTAMP
+                ORDER BY time DESC LIMIT {limit}"
+        );
+        Ok(params
+            .bind(sqlx::query(query.as_str()))
             .fetch_all(&self.pool)
             .await?
             .into_iter()
             .map(|row| {
-                let mut ret_line: Vec<serde_json::Value> = Vec::new();
-                for _i in 2..col_number + 2 {
-                    ret_line.push(match row.try_get::<serde_json::Value, _>(2) {
-                        Ok(val) => val,
-                        Err(_) => match row.try_get::<f64, _>(2) {
-                            Ok(val) => val.into(),
-                            Err(_) => match row.try_get::<Vec<String>, _>(2) {
-                                Ok(strval) => strval.into(),
-                                Err(_) => match row.try_get::<String, _>(2) {
-                                    Ok(strval) => strval.into(),
-                                    Err(_) => serde_json::from_str("null").unwrap(),
-                                },
-                            },
-                        },
-                    })
-                }
                 (
                     row.get::<NaiveDateTime, _>(0),
-                    row.get::<String, _>(1),
-                    ret_line,
+                    row.get::<Option<String>, _>(1).unwrap_or_default(),
+                    row.get::<Option<String>, _>(2),
+                    row.get::<Option<serde_json::Value>, _>(3)
+                        .unwrap_or(serde_json::Value::Null),
                 )
             })
             .collect())
     }
 
+    /// Logs of a view inserted after row `after_id`, but for the `exclude` ids,
+    /// in insertion order.
+    pub async fn tail_logs(
+        &self,
+        table: &str,
+        search: Option<(WordMatch, &[String])>,
+        after_id: i64,
+        exclude: &[i64],
+        limit: i64,
+    ) -> Result<Vec<(i64, LogLine)>, AppError> {
+        // $1 is the ids to exclude
+        let mut params = BoundParams::new(1);
+        let (col_number, column_sql, where_sql) = self.view_sql(table, search, &mut params).await?;
+        let query = format!(
+            "SELECT id, time, level, {column_sql} from logs
+                WHERE {where_sql} AND id > {after_id} AND id <> ALL($1::bigint[])
+                ORDER BY id LIMIT {limit}"
+        );
+        Ok(params
+            .bind(sqlx::query(query.as_str()).bind(exclude))
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(|row| Ok((row.try_get::<i64, _>(0)?, log_line(row, 1, col_number)?)))
+            .collect::<Result<_, sqlx::Error>>()?)
+    }
+
     pub async fn get_density(
         &self,
         start: chrono::NaiveDateTime,
         end: chrono::NaiveDateTime,
         table: &str,
-    ) -> Result<Vec<i64>, sqlx::error::Error> {
+    ) -> Result<Vec<i64>, AppError> {
+        // Only existing views are read, names are checked when they are saved
+        let where_query = self.get_filter(table.to_owned()).await?;
         let interval_millis = (end - start).num_milliseconds();
-        let interval_micro = (end - start).num_microseconds();
-        let interval_str = match interval_micro {
-            Some(val) => format!("{} microseconds", max(val / 119, 10)),
-            None => format!("{} milliseconds", max(interval_millis / 119, 10)),
-        };
+        let interval_str = bucket_interval(start, end);
+        let mut params = BoundParams::new(0);
         let query = match interval_millis {
             0..=100000 => {
-                let where_query = sqlx::query("SELECT query from filters WHERE name = $1")
-                    .bind(table)
-                    .fetch_one(&self.pool)
-                    .await?
-                    .try_get::<String, _>(0)?;
+                let where_sql = filter::parse_filter(&where_query)?.to_sql(&mut params);
                 format!(
                     "
                 SELECT COUNT(*)::bigint
@@ -275,39 +1167,81 @@ impl Repository {
                       AND time <= '{}'::TIMESTAMP
                     GROUP BY time_bucket_gapfill('{}', time)
                     LIMIT 120",
-                    where_query, start, end, interval_str
+                    where_sql, start, end, interval_str
                 )
             }
             100001..=10000000 => {
                 format!(
                     "
                 SELECT sum(count)::bigint
-                    FROM {}_sec_count
+                    FROM {}
                     WHERE time_bucket >= '{}'::TIMESTAMP
                       AND time_bucket <= '{}'::TIMESTAMP
                     GROUP BY time_bucket_gapfill('{}', time_bucket)
                     LIMIT 120",
-                    table, start, end, interval_str
+                    quote_ident(&format!("{}_sec_count", table)),
+                    start,
+                    end,
+                    interval_str
                 )
             }
             _ => {
                 format!(
                     "
                 SELECT sum(count)::bigint
-                    FROM {}_min_count
+                    FROM {}
                     WHERE time_bucket >= '{}'::TIMESTAMP
                       AND time_bucket <= '{}'::TIMESTAMP
                     GROUP BY time_bucket_gapfill('{}', time_bucket)
                     LIMIT 120",
-                    table, start, end, interval_str
+                    quote_ident(&format!("{}_min_count", table)),
+                    start,
+                    end,
+                    interval_str
                 )
             }
         };
-        Ok(sqlx::query(query.as_str())
+        Ok(params
+            .bind(sqlx::query(query.as_str()))
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| row.try_get::<i64, _>(0).unwrap_or(0))
+            .collect())
+    }
+
+    /// Log counts over time for a word search. Aggregates cannot apply the
+    /// search, so this always reads from `logs` through the words index.
+    pub async fn get_search_density(
+        &self,
+        start: chrono::NaiveDateTime,
+        end: chrono::NaiveDateTime,
+        table: &str,
+        mode: WordMatch,
+        terms: &[String],
+    ) -> Result<Vec<i64>, AppError> {
+        let interval_str = bucket_interval(start, end);
+        let where_query = self.get_filter(table.to_owned()).await?;
+        let mut params = BoundParams::new(0);
+        let where_sql = filter::parse_filter(&where_query)?.to_sql(&mut params);
+        let words_sql = mode.to_sql(terms, &mut params);
+        let query = format!(
+            "
+        SELECT COUNT(*)::bigint
+            FROM logs
+            WHERE {where_sql}
+              AND {words_sql}
+              AND time >= '{start}'::TIMESTAMP
+              AND time <= '{end}'::TIMESTAMP
+            GROUP BY time_bucket_gapfill('{interval_str}', time)
+            LIMIT 120"
+        );
+        Ok(params
+            .bind(sqlx::query(query.as_str()))
             .fetch_all(&self.pool)
             .await?
             .into_iter()
-            .map(|row| row.try_get::<i64, _>(0).unwrap_or(0).into())
+            .map(|row| row.try_get::<i64, _>(0).unwrap_or(0))
             .collect())
     }
 }
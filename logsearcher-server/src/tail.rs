@@ -0,0 +1,275 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    convert::Infallible,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::response::sse::Event;
+use futures::{stream, Stream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::filter::WordMatch;
+use crate::repository::Repository;
+
+/// Shared limits for live tail streams.
+#[derive(Clone)]
+pub struct Tail {
+    slots: Arc<Semaphore>,
+    pub poll_interval: Duration,
+    pub batch_size: i64,
+    /// How long a transaction may keep ids it took before its rows are
+    /// skipped, past which a stuck transaction no longer holds the stream back.
+    pub max_commit_wait: Duration,
+    /// Most streamed ids kept above the settled id, past which the oldest
+    /// are settled early, as with a transaction stuck for too long.
+    pub max_sent: usize,
+}
+
+impl Tail {
+    pub fn new(max_clients: usize, poll_interval: Duration) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(max_clients)),
+            poll_interval,
+            batch_size: 500,
+            max_commit_wait: Duration::from_secs(60),
+            max_sent: 50_000,
+        }
+    }
+
+    /// Reserve a client slot, released when the stream is dropped.
+    pub fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.slots.clone().try_acquire_owned().ok()
+    }
+}
+
+impl Default for Tail {
+    fn default() -> Self {
+        Self::new(32, Duration::from_secs(1))
+    }
+}
+
+struct TailState {
+    db: Repository,
+    table: String,
+    search: Option<(WordMatch, Vec<String>)>,
+    /// Every row up to this id is committed and was streamed
+    last_id: i64,
+    /// Ids above `last_id` already streamed
+    sent: BTreeSet<i64>,
+    /// `(xmax, last id handed out, taken at)` of the previous polls, oldest first
+    checkpoints: VecDeque<(i64, i64, Instant)>,
+    pending: VecDeque<Event>,
+    poll_interval: Duration,
+    batch_size: i64,
+    max_commit_wait: Duration,
+    max_sent: usize,
+    full_batch: bool,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl TailState {
+    /// Highest id up to which every row is committed, given the oldest
+    /// transaction still running.
+    fn settled_id(&mut self, xmin: i64) -> i64 {
+        let mut settled = self.last_id;
+        while let Some(&(xmax, last_id, taken_at)) = self.checkpoints.front() {
+            if xmin < xmax && taken_at.elapsed() < self.max_commit_wait {
+                break;
+            }
+            settled = settled.max(last_id);
+            self.checkpoints.pop_front();
+        }
+        settled
+    }
+}
+
+/// Stream logs of a view inserted after `last_id` as server-sent events.
+///
+/// The database is only polled once the previous batch has been sent, so a
+/// slow client slows its own polling down instead of buffering rows.
+///
+/// Rows can commit out of id order, as several writers insert at once. The
+/// stream only moves `last_id` past ids whose transactions are over, and
+/// remembers the rows it sent above it, so a late commit is still streamed.
+pub fn log_stream(
+    db: Repository,
+    tail: &Tail,
+    permit: OwnedSemaphorePermit,
+    table: String,
+    search: Option<(WordMatch, Vec<String>)>,
+    last_id: i64,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    let state = TailState {
+        db,
+        table,
+        search,
+        last_id,
+        sent: BTreeSet::new(),
+        checkpoints: VecDeque::new(),
+        pending: VecDeque::new(),
+        poll_interval: tail.poll_interval,
+        batch_size: tail.batch_size,
+        max_commit_wait: tail.max_commit_wait,
+        max_sent: tail.max_sent,
+        full_batch: false,
+        _permit: permit,
+    };
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(event) = state.pending.pop_front() {
+                return Some((Ok(event), state));
+            }
+            if !state.full_batch {
+                tokio::time::sleep(state.poll_interval).await;
+            }
+            let (xmin, xmax, last_id) = match state.db.tail_checkpoint().await {
+                Ok(checkpoint) => checkpoint,
+                Err(err) => {
+                    tracing::error!(message = "tail query failed", error = ?err);
+                    return None;
+                }
+            };
+            let settled = state.settled_id(xmin);
+            state.checkpoints.push_back((xmax, last_id, Instant::now()));
+            let search = state
+                .search
+                .as_ref()
+                .map(|(mode, terms)| (*mode, terms.as_slice()));
+            let sent: Vec<i64> = state.sent.iter().copied().collect();
+            let rows = match state
+                .db
+                .tail_logs(&state.table, search, state.last_id, &sent, state.batch_size)
+                .await
+            {
+                Ok(rows) => rows,
+                Err(err) => {
+                    tracing::error!(message = "tail query failed", error = ?err);
+                    return None;
+                }
+            };
+            state.full_batch = rows.len() as i64 == state.batch_size;
+            // A full batch may have left settled rows out, past its last id.
+            state.last_id = match rows.last() {
+                Some((id, _)) if state.full_batch => settled.min(*id),
+                _ => settled,
+            };
+            for (id, line) in rows {
+                state.sent.insert(id);
+                let event = Event::default()
+                    .event("log")
+                    .id(id.to_string())
+                    .json_data(line)
+                    .unwrap_or_default();
+                state.pending.push_back(event);
+            }
+            // Ids are only kept until every transaction that took an id
+            // below them is over, or there are too many of them
+            state.sent = state.sent.split_off(&(state.last_id + 1));
+            if state.sent.len() > state.max_sent {
+                let excess = state.sent.len() - state.max_sent;
+                state.last_id = *state.sent.iter().nth(excess - 1).unwrap();
+                state.sent = state.sent.split_off(&(state.last_id + 1));
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    async fn next_event(
+        stream: &mut (impl Stream<Item = Result<Event, Infallible>> + Unpin),
+    ) -> String {
+        let event = tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .expect("A log should be streamed")
+            .unwrap()
+            .unwrap();
+        format!("{:?}", event)
+    }
+
+    #[sqlx::test]
+    async fn test_log_stream_late_commit(pool: sqlx::PgPool) {
+        let insert =
+            "INSERT INTO logs (time, level, logdata) VALUES ('2024-03-24 17:54:00', 'INFO', $1)";
+        let tail = Tail::new(1, Duration::from_millis(10));
+        let permit = tail.try_acquire().unwrap();
+        let stream = log_stream(
+            Repository { pool: pool.clone() },
+            &tail,
+            permit,
+            "logs".to_owned(),
+            None,
+            0,
+        );
+        let mut stream = Box::pin(stream);
+
+        // The first row takes the lower id but commits after the second one.
+        let mut transaction = pool.begin().await.unwrap();
+        sqlx::query(insert)
+            .bind(serde_json::json!({"n": 1}))
+            .execute(&mut *transaction)
+            .await
+            .unwrap();
+        sqlx::query(insert)
+            .bind(serde_json::json!({"n": 2}))
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(next_event(&mut stream).await.contains(r#"{\"n\":2}"#));
+        // Keep polling while the first row is not committed yet.
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), stream.next())
+                .await
+                .is_err()
+        );
+        transaction.commit().await.unwrap();
+        assert!(next_event(&mut stream).await.contains(r#"{\"n\":1}"#));
+    }
+
+    #[sqlx::test]
+    async fn test_log_stream_max_sent(pool: sqlx::PgPool) {
+        let insert =
+            "INSERT INTO logs (time, level, logdata) VALUES ('2024-03-24 17:54:00', 'INFO', $1)";
+        let mut tail = Tail::new(1, Duration::from_millis(10));
+        tail.max_sent = 1;
+        let permit = tail.try_acquire().unwrap();
+        let stream = log_stream(
+            Repository { pool: pool.clone() },
+            &tail,
+            permit,
+            "logs".to_owned(),
+            None,
+            0,
+        );
+        let mut stream = Box::pin(stream);
+
+        // The first row holds the stream back until more ids than max_sent
+        // were streamed after it.
+        let mut transaction = pool.begin().await.unwrap();
+        sqlx::query(insert)
+            .bind(serde_json::json!({"n": 1}))
+            .execute(&mut *transaction)
+            .await
+            .unwrap();
+        for n in [2, 3] {
+            sqlx::query(insert)
+                .bind(serde_json::json!({"n": n}))
+                .execute(&pool)
+                .await
+                .unwrap();
+            let expected = format!(r#"{{\"n\":{}}}"#, n);
+            assert!(next_event(&mut stream).await.contains(&expected));
+        }
+        // Nothing is streamed twice, and the first row was settled early.
+        transaction.commit().await.unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), stream.next())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -23,6 +23,8 @@ export const useLogStore = defineStore('logstore', {
         let start = ref(new Date('05 October 2022 14:48 UTC'))
         let end = ref(new Date())
         let logs = ref([])
+        // Cursor of the next page of logs, null once every page is loaded
+        let nextCursor: string | null = null
         let currentView: View = { name: 'logs', cols: [{ metric: 'Data', agg: '' }] }
         let metrics: Ref<{
             [k: string]: Metric
@@ -42,7 +44,7 @@ export const useLogStore = defineStore('logstore', {
         let viewName: String = 'logs'
 
 
-        return { start, end, logs, metrics, metric_agg, loading, viewName, currentView }
+        return { start, end, logs, nextCursor, metrics, metric_agg, loading, viewName, currentView }
     },
     getters: {
         graphics() {
@@ -59,7 +61,9 @@ export const useLogStore = defineStore('logstore', {
     actions: {
         update() {
             this.loading = true
+            this.viewName = this.currentView.name
             this.logs = []
+            this.nextCursor = null
             this.metrics.NumberOfLogs.data = new Array(120).fill(0)
             fetch("/api/density", {
                 method: "POST",
@@ -83,7 +87,7 @@ export const useLogStore = defineStore('logstore', {
                 body: JSON.stringify({ start: this.start.toJSON(), end: this.end.toJSON(), table: this.viewName }),
                 headers: { "Content-Type": "application/json" }
             }
-            ).then((resp) => resp.json().then((obj) => { this.logs = obj, this.loading = false }, () => this.loading = false), () => this.loading = false)
+            ).then((resp) => resp.json().then((obj) => { this.logs = obj.logs, this.nextCursor = obj.next_cursor, this.loading = false }, () => this.loading = false), () => this.loading = false)
         }
 
     }